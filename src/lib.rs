@@ -0,0 +1,3345 @@
+//! The delay behind jack-delay, independent of JACK so it can be embedded in other clients.
+//!
+//! A [`DelayLine`] delays a single channel through any number of taps, with feedback,
+//! modulation and filtering set up through its setters. A [`StereoDelay`] runs two of
+//! them side by side on the buffers of a period, e.g. in a JACK process callback:
+//!
+//! ```
+//! use jack_delay::{DelayLine, StereoDelay};
+//!
+//! // a single tap two frames late, panned to the side of its channel
+//! let mut lines = [DelayLine::new(0), DelayLine::new(0)];
+//! for (line, pan) in lines.iter_mut().zip([-1.0, 1.0]) {
+//!     line.add_tap(2.0, 1.0, pan);
+//! }
+//! let mut delay = StereoDelay::new(lines);
+//! delay.set_fade_in(0);
+//!
+//! let input = [1.0, 0.0, 0.0, 0.0];
+//! let (mut left, mut right) = ([0.0; 4], [0.0; 4]);
+//! delay.process([&input, &[0.0; 4]], [&mut left, &mut right], None, None, None);
+//! assert_eq!(left, [0.0, 0.0, 1.0, 0.0]);
+//! ```
+//!
+//! A [`DelayMatrix`] routes any number of inputs to any number of outputs instead, each path
+//! with its own delay and gain, and a [`MidiDelay`] delays MIDI events alongside the audio.
+//! A [`DriftResampler`] carries the output over to a device running on a clock of its own.
+//!
+//! Nothing allocates after setting up, so processing is safe on a real-time thread.
+//! Changing a delay while running stays within the buffer reserved up to then.
+
+use std::collections::VecDeque;
+use std::os::unix::io::AsRawFd;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Sample type of the lines, filters and feedback paths. Buffers and parameters
+/// stay `f32` and are converted on the way in and out, the `f64` feature trades
+/// some speed for less accumulated rounding error.
+#[cfg(not(feature = "f64"))]
+pub type Sample = f32;
+#[cfg(feature = "f64")]
+pub type Sample = f64;
+
+/// Rounds a sample for an output buffer.
+#[allow(clippy::unnecessary_cast)] // a no-op without the f64 feature
+fn to_f32 (sample: Sample) -> f32 {
+	sample as f32
+}
+
+/// Widens a sample for computing delay times.
+#[allow(clippy::unnecessary_cast)] // a no-op with the f64 feature
+fn to_f64 (sample: Sample) -> f64 {
+	sample as f64
+}
+
+/// Tempo used to resolve note valued delays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tempo {
+	pub bpm: f64,
+	/// note value of a single beat, e.g. 4 for quarter notes
+	pub beat_type: f64,
+}
+
+/// Converts a duration in milliseconds to frames.
+pub fn millis_to_frames (ms: f64, sample_rate: usize) -> f64 {
+	ms * sample_rate as f64 / 1000.0
+}
+
+/// Converts decibels to a linear gain factor.
+pub fn db_to_gain (db: f32) -> f32 {
+	10f32.powf(db / 20.0)
+}
+
+/// Coefficient of a one pole smoother reaching about 63% of a step after `ms`.
+pub fn smoothing_coefficient (ms: f64, sample_rate: usize) -> f32 {
+	let frames = millis_to_frames(ms, sample_rate);
+	if frames < 1.0 {
+		1.0
+	} else {
+		(1.0 - (-1.0 / frames).exp()) as f32
+	}
+}
+
+const METERS_PER_FOOT: f64 = 0.3048;
+
+/// Approximate speed of sound in meters per second at the given
+/// air temperature in °C and relative humidity in percent.
+pub fn speed_of_sound (temperature: f64, humidity: f64) -> f64 {
+	331.3 + 0.606 * temperature + 0.0124 * humidity
+}
+
+/// Everything needed to turn a `DelayTime` into frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timebase {
+	pub sample_rate: usize,
+	pub tempo: Tempo,
+	/// meters per second
+	pub speed_of_sound: f64,
+}
+
+/// A delay amount as given on the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DelayTime {
+	Frames(f64),
+	Millis(f64),
+	Seconds(f64),
+	/// fraction of a whole note
+	Note(f64),
+	/// meters travelled by sound
+	Distance(f64),
+}
+
+impl DelayTime {
+	/// Converts the delay to a (possibly fractional) number of frames.
+	pub fn to_frames (self, timebase: &Timebase) -> f64 {
+		let Timebase { sample_rate, tempo, speed_of_sound } = *timebase;
+		match self {
+			DelayTime::Frames(frames) => frames,
+			DelayTime::Millis(ms) => millis_to_frames(ms, sample_rate),
+			DelayTime::Seconds(s) => s * sample_rate as f64,
+			DelayTime::Note(fraction) => {
+				let beat_seconds = 60.0 / tempo.bpm;
+				fraction * tempo.beat_type * beat_seconds * sample_rate as f64
+			}
+			DelayTime::Distance(meters) => meters / speed_of_sound * sample_rate as f64,
+		}
+	}
+
+	/// The straight, dotted or triplet note value closest to `seconds` at the given tempo.
+	pub fn nearest_note (seconds: f64, tempo: Tempo) -> Self {
+		const NOTES: [f64; 12] = [
+			1.0 / 16.0, 1.0 / 12.0, 3.0 / 32.0,
+			1.0 / 8.0, 1.0 / 6.0, 3.0 / 16.0,
+			1.0 / 4.0, 1.0 / 3.0, 3.0 / 8.0,
+			1.0 / 2.0, 3.0 / 4.0, 1.0,
+		];
+		let whole = tempo.beat_type * 60.0 / tempo.bpm;
+		let fraction = seconds / whole;
+		let distance = |note: f64| (fraction / note).ln().abs();
+		let nearest = NOTES
+			.iter()
+			.copied()
+			.fold(NOTES[0], |best, note| if distance(note) < distance(best) { note } else { best });
+		DelayTime::Note(nearest)
+	}
+
+	/// The same kind of delay, `factor` times as long.
+	pub fn scaled (self, factor: f64) -> DelayTime {
+		match self {
+			DelayTime::Frames(frames) => DelayTime::Frames(frames * factor),
+			DelayTime::Millis(ms) => DelayTime::Millis(ms * factor),
+			DelayTime::Seconds(seconds) => DelayTime::Seconds(seconds * factor),
+			DelayTime::Note(fraction) => DelayTime::Note(fraction * factor),
+			DelayTime::Distance(meters) => DelayTime::Distance(meters * factor),
+		}
+	}
+
+	/// Whether the delay has to be recomputed when the tempo changes.
+	pub fn is_tempo_synced (self) -> bool {
+		matches!(self, DelayTime::Note(_))
+	}
+}
+
+impl FromStr for DelayTime {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		if let Some((numerator, denominator)) = s.split_once('/') {
+			// dotted notes are half again as long, triplets take two thirds
+			let (denominator, length) = match denominator.strip_suffix('d') {
+				Some(denominator) => (denominator, 1.5),
+				None => match denominator.strip_suffix('t') {
+					Some(denominator) => (denominator, 2.0 / 3.0),
+					None => (denominator, 1.0),
+				},
+			};
+			return match (numerator.parse::<f64>(), denominator.parse::<f64>()) {
+				(Ok(numerator), Ok(denominator)) if numerator > 0.0 && denominator > 0.0 => {
+					Ok(DelayTime::Note(numerator / denominator * length))
+				}
+				_ => Err(format!("invalid note value \"{}\", expected e.g. 1/4, 3/8, 1/8d or 1/8t", s)),
+			};
+		}
+		// a leading minus asks for latency compensation
+		let sign = if s.starts_with('-') { 1 } else { 0 };
+		let number_end = s[sign..]
+			.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+			.map_or(s.len(), |end| end + sign);
+		let (number, unit) = s.split_at(number_end);
+		let invalid = || format!("invalid delay \"{}\", expected e.g. 4800, 120ms, 0.5s, 1/4 or 12.5m", s);
+
+		match unit.trim() {
+			"" | "f" | "frames" => number.parse().map(DelayTime::Frames).map_err(|_| invalid()),
+			"ms" => number.parse().map(DelayTime::Millis).map_err(|_| invalid()),
+			"s" => number.parse().map(DelayTime::Seconds).map_err(|_| invalid()),
+			"m" => number.parse().map(DelayTime::Distance).map_err(|_| invalid()),
+			"ft" | "feet" => number
+				.parse::<f64>()
+				.map(|feet| DelayTime::Distance(feet * METERS_PER_FOOT))
+				.map_err(|_| invalid()),
+			_ => Err(invalid()),
+		}
+	}
+}
+
+/// How a tap moves to a new delay.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeMode {
+	/// fade from the old read position to the new one
+	Crossfade,
+	/// glide the read position, bending the pitch like a tape delay
+	Repitch,
+}
+
+impl FromStr for ChangeMode {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"crossfade" => Ok(ChangeMode::Crossfade),
+			"repitch" => Ok(ChangeMode::Repitch),
+			_ => Err(format!("unknown change mode \"{}\", expected crossfade or repitch", s)),
+		}
+	}
+}
+
+/// How a fractional read position is resolved.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+	Linear,
+	/// first order thiran allpass, flat magnitude response but
+	/// phase distortion close to nyquist
+	Allpass,
+	/// windowed sinc over the given number of points
+	Sinc(usize),
+}
+
+impl FromStr for Interpolation {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"linear" => Ok(Interpolation::Linear),
+			"allpass" => Ok(Interpolation::Allpass),
+			"sinc" => Ok(Interpolation::Sinc(32)),
+			_ => match s.strip_prefix("sinc:").map(str::parse::<usize>) {
+				Some(Ok(points)) if (4..=256).contains(&points) && points % 2 == 0 => {
+					Ok(Interpolation::Sinc(points))
+				}
+				Some(_) => Err(format!("invalid sinc length in \"{}\", expected an even number of points from 4 to 256", s)),
+				None => Err(format!("unknown interpolation \"{}\", expected linear, allpass or sinc:<points>", s)),
+			},
+		}
+	}
+}
+
+/// Windowed sinc kernels precomputed for a range of fractional positions,
+/// so the process callback only looks them up.
+struct SincTable {
+	points: usize,
+	/// number of fractional positions between two frames
+	phases: usize,
+	/// `phases + 1` kernels of `points` coefficients each, from the oldest frame to the newest
+	coefficients: Vec<Sample>,
+}
+
+impl SincTable {
+	fn new (points: usize) -> Self {
+		let phases = 512;
+		let half = (points / 2) as f64;
+		let mut coefficients = Vec::with_capacity((phases + 1) * points);
+		for phase in 0..=phases {
+			let frac = phase as f64 / phases as f64;
+			let kernel: Vec<f64> = (0..points)
+				.map(|j| {
+					// distance of the frame j from the read position
+					let x = j as f64 - (half - 1.0) - frac;
+					let sinc = if x == 0.0 {
+						1.0
+					} else {
+						(std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+					};
+					let w = std::f64::consts::PI * x / half;
+					let blackman = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+					sinc * blackman
+				})
+				.collect();
+			// normalize for unity gain at dc. reversed, so the kernel runs
+			// in the same direction as the frames in the buffer.
+			let sum: f64 = kernel.iter().sum();
+			coefficients.extend(kernel.iter().rev().map(|c| (c / sum) as Sample));
+		}
+		SincTable { points, phases, coefficients }
+	}
+
+	/// The kernel for a fractional position, rounded to the nearest phase.
+	fn kernel (&self, frac: f64) -> &[Sample] {
+		let phase = (frac * self.phases as f64).round() as usize;
+		&self.coefficients[phase * self.points..(phase + 1) * self.points]
+	}
+}
+
+/// Sum of the products of `a` and `b`, vectorized if the cpu supports it.
+fn dot (a: &[Sample], b: &[Sample]) -> Sample {
+	#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+	{
+		if is_x86_feature_detected!("avx") {
+			// safe, the cpu was just checked for avx
+			return unsafe { dot_avx(a, b) };
+		}
+	}
+	dot_scalar(a, b)
+}
+
+fn dot_scalar (a: &[Sample], b: &[Sample]) -> Sample {
+	a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// `dot` eight frames at a time.
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+#[target_feature(enable = "avx")]
+unsafe fn dot_avx (a: &[Sample], b: &[Sample]) -> Sample {
+	use std::arch::x86_64::*;
+	let len = a.len().min(b.len());
+	let vectorized = len - len % 8;
+	let mut sums = _mm256_setzero_ps();
+	for start in (0..vectorized).step_by(8) {
+		let products = _mm256_mul_ps(_mm256_loadu_ps(a.as_ptr().add(start)), _mm256_loadu_ps(b.as_ptr().add(start)));
+		sums = _mm256_add_ps(sums, products);
+	}
+	let mut lanes = [0.0; 8];
+	_mm256_storeu_ps(lanes.as_mut_ptr(), sums);
+	lanes.iter().sum::<Sample>() + dot_scalar(&a[vectorized..len], &b[vectorized..len])
+}
+
+/// Copies the frames of a port into a buffer of samples.
+fn copy_in (buffer: &mut [Sample], input: &[f32]) {
+	#[cfg(not(feature = "f64"))]
+	buffer.copy_from_slice(input);
+	#[cfg(feature = "f64")]
+	for (sample, &frame) in buffer.iter_mut().zip(input) {
+		*sample = frame as Sample;
+	}
+}
+
+/// Adds the frames interpolated between `newer` and `older` by `frac` and scaled
+/// by `gain` onto `out`, vectorized if the cpu supports it.
+fn mix_interpolated (out: &mut [f32], newer: &[Sample], older: &[Sample], frac: Sample, gain: Sample) {
+	#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+	{
+		if is_x86_feature_detected!("avx") {
+			// safe, the cpu was just checked for avx
+			return unsafe { mix_interpolated_avx(out, newer, older, frac, gain) };
+		}
+	}
+	mix_interpolated_scalar(out, newer, older, frac, gain)
+}
+
+fn mix_interpolated_scalar (out: &mut [f32], newer: &[Sample], older: &[Sample], frac: Sample, gain: Sample) {
+	for ((sample, &newer), &older) in out.iter_mut().zip(newer).zip(older) {
+		*sample += to_f32((newer * (1.0 - frac) + older * frac) * gain);
+	}
+}
+
+/// `mix_interpolated` eight frames at a time.
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+#[target_feature(enable = "avx")]
+unsafe fn mix_interpolated_avx (out: &mut [f32], newer: &[Sample], older: &[Sample], frac: Sample, gain: Sample) {
+	use std::arch::x86_64::*;
+	let len = out.len().min(newer.len()).min(older.len());
+	let vectorized = len - len % 8;
+	let (newer_gain, older_gain) = (_mm256_set1_ps((1.0 - frac) * gain), _mm256_set1_ps(frac * gain));
+	for start in (0..vectorized).step_by(8) {
+		let newer = _mm256_mul_ps(_mm256_loadu_ps(newer.as_ptr().add(start)), newer_gain);
+		let older = _mm256_mul_ps(_mm256_loadu_ps(older.as_ptr().add(start)), older_gain);
+		let sum = _mm256_add_ps(_mm256_loadu_ps(out.as_ptr().add(start)), _mm256_add_ps(newer, older));
+		_mm256_storeu_ps(out.as_mut_ptr().add(start), sum);
+	}
+	mix_interpolated_scalar(&mut out[vectorized..len], &newer[vectorized..len], &older[vectorized..len], frac, gain);
+}
+
+/// Moves `out` towards `dry` by `mix`, vectorized if the cpu supports it.
+fn blend (out: &mut [f32], dry: &[f32], mix: f32) {
+	#[cfg(target_arch = "x86_64")]
+	{
+		if is_x86_feature_detected!("avx") {
+			// safe, the cpu was just checked for avx
+			return unsafe { blend_avx(out, dry, mix) };
+		}
+	}
+	blend_scalar(out, dry, mix)
+}
+
+fn blend_scalar (out: &mut [f32], dry: &[f32], mix: f32) {
+	for (sample, &dry) in out.iter_mut().zip(dry) {
+		*sample += (dry - *sample) * mix;
+	}
+}
+
+/// `blend` eight frames at a time.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn blend_avx (out: &mut [f32], dry: &[f32], mix: f32) {
+	use std::arch::x86_64::*;
+	let len = out.len().min(dry.len());
+	let vectorized = len - len % 8;
+	let mix_8 = _mm256_set1_ps(mix);
+	for start in (0..vectorized).step_by(8) {
+		let wet = _mm256_loadu_ps(out.as_ptr().add(start));
+		let difference = _mm256_sub_ps(_mm256_loadu_ps(dry.as_ptr().add(start)), wet);
+		_mm256_storeu_ps(out.as_mut_ptr().add(start), _mm256_add_ps(wet, _mm256_mul_ps(difference, mix_8)));
+	}
+	blend_scalar(&mut out[vectorized..len], &dry[vectorized..len], mix);
+}
+
+/// A single read position on a delay line.
+#[derive(Clone, Copy, Debug)]
+struct Tap {
+	delay: f64,
+	gain: Smoothed,
+	/// share of the tap on the left and the right output
+	pan_gains: [Sample; 2],
+	/// delay that is being faded out after a change
+	previous_delay: f64,
+	fade_remaining: usize,
+	/// length of the running fade, the glide time may change while it runs
+	fade_length: usize,
+	/// position within the current window in reverse or shuffle mode
+	window_pos: usize,
+	/// extra delay of the current grain in shuffle mode
+	grain_offset: usize,
+	/// interpolator memory of the current and the faded out read position
+	interpolation_state: [Sample; 2],
+}
+
+impl Tap {
+	/// The delay a gliding read position is currently at.
+	fn gliding_delay (&self) -> f64 {
+		if self.fade_remaining == 0 {
+			return self.delay;
+		}
+		let progress = 1.0 - self.fade_remaining as f64 / self.fade_length as f64;
+		self.previous_delay + (self.delay - self.previous_delay) * progress
+	}
+}
+
+/// Advances a xorshift generator and returns the new state.
+fn xorshift (state: &mut u32) -> u32 {
+	*state ^= *state << 13;
+	*state ^= *state >> 17;
+	*state ^= *state << 5;
+	*state
+}
+
+/// Waveform of the modulation lfo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LfoShape {
+	Sine,
+	Triangle,
+	Ramp,
+	/// a new random value at the start of every cycle
+	Random,
+}
+
+impl FromStr for LfoShape {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"sine" => Ok(LfoShape::Sine),
+			"triangle" => Ok(LfoShape::Triangle),
+			"ramp" => Ok(LfoShape::Ramp),
+			"random" => Ok(LfoShape::Random),
+			_ => Err(format!("unknown lfo shape \"{}\", expected sine, triangle, ramp or random", s)),
+		}
+	}
+}
+
+/// A low frequency oscillator driving the delay time modulation.
+pub struct Lfo {
+	shape: LfoShape,
+	/// cycles per second
+	rate: f64,
+	/// position within the cycle, 0.0 - 1.0
+	phase: f64,
+	/// phase increment per frame
+	increment: f64,
+	/// value held by the random shape
+	held: f64,
+	/// xorshift state for the random shape
+	seed: u32,
+}
+
+impl Lfo {
+	/// Creates an lfo starting at `phase` (0.0 - 1.0) into its cycle.
+	pub fn new (rate: f64, shape: LfoShape, phase: f64, sample_rate: usize) -> Self {
+		Lfo {
+			shape,
+			rate,
+			phase: phase.rem_euclid(1.0),
+			increment: rate / sample_rate as f64,
+			held: 0.0,
+			// offset channels should not hold the same random values
+			seed: 0x9e37_79b9 ^ (phase.rem_euclid(1.0) * u32::MAX as f64) as u32,
+		}
+	}
+
+	/// Keeps the rate in cycles per second at `sample_rate`, carrying on from the current phase.
+	pub fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.increment = self.rate / sample_rate as f64;
+	}
+
+	fn random (&mut self) -> f64 {
+		xorshift(&mut self.seed) as f64 / u32::MAX as f64 * 2.0 - 1.0
+	}
+
+	/// Advances by one frame and returns the value in the range -1.0 - 1.0.
+	fn next (&mut self) -> f64 {
+		let value = match self.shape {
+			LfoShape::Sine => (self.phase * std::f64::consts::TAU).sin(),
+			LfoShape::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+			LfoShape::Ramp => self.phase * 2.0 - 1.0,
+			LfoShape::Random => self.held,
+		};
+		let phase = self.phase + self.increment;
+		if phase >= 1.0 && self.shape == LfoShape::Random {
+			self.held = self.random();
+		}
+		self.phase = phase.fract();
+		value
+	}
+}
+
+/// Shifts the read positions by a random amount that is picked anew for every repeat.
+struct Humanize {
+	/// largest shift in frames
+	depth: f64,
+	/// shift picked for the current repeat
+	target: f64,
+	/// shift at the current frame, gliding towards `target`
+	shift: f64,
+	/// frames left until the next repeat begins
+	remaining: usize,
+	seed: u32,
+}
+
+impl Humanize {
+	fn new (depth: f64, seed: u32) -> Self {
+		Humanize {
+			depth,
+			target: 0.0,
+			shift: 0.0,
+			remaining: 0,
+			seed: seed.max(1),
+		}
+	}
+
+	/// Advances by one frame of a repeat `period` frames long and returns the shift in frames.
+	fn next (&mut self, period: f64) -> f64 {
+		if self.remaining == 0 {
+			self.remaining = period.round().max(1.0) as usize;
+			self.target = xorshift(&mut self.seed) as f64 / u32::MAX as f64 * self.depth;
+		}
+		self.remaining -= 1;
+		// glide over about a quarter of the repeat, bending the pitch slightly instead of clicking
+		self.shift += (self.target - self.shift) * (4.0 / period.max(4.0));
+		self.shift
+	}
+}
+
+/// Random values between 0.0 and 1.0, gliding from one to the next with a cosine curve.
+struct SmoothNoise {
+	/// new values per second
+	rate: f64,
+	/// position between `from` and `to`, 0.0 - 1.0
+	phase: f64,
+	/// change of `phase` per frame
+	increment: f64,
+	from: f64,
+	to: f64,
+	seed: u32,
+}
+
+impl SmoothNoise {
+	/// Creates noise picking `rate` new values per second.
+	fn new (rate: f64, seed: u32, sample_rate: usize) -> Self {
+		SmoothNoise {
+			rate,
+			phase: 0.0,
+			increment: rate / sample_rate as f64,
+			from: 0.5,
+			to: 0.5,
+			seed: seed.max(1),
+		}
+	}
+
+	fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.increment = self.rate / sample_rate as f64;
+	}
+
+	fn next (&mut self) -> f64 {
+		self.phase += self.increment;
+		if self.phase >= 1.0 {
+			self.phase = self.phase.fract();
+			self.from = self.to;
+			self.to = xorshift(&mut self.seed) as f64 / u32::MAX as f64;
+		}
+		let curve = (1.0 - (self.phase * std::f64::consts::PI).cos()) / 2.0;
+		self.from + (self.to - self.from) * curve
+	}
+}
+
+/// Slow (wow) and fast (flutter) random drift of the read positions, like a tape transport.
+pub struct WowFlutter {
+	wow: SmoothNoise,
+	flutter: SmoothNoise,
+	/// largest drift of each in frames
+	wow_depth: f64,
+	flutter_depth: f64,
+}
+
+impl WowFlutter {
+	/// changes of direction per second
+	const WOW_RATE: f64 = 0.7;
+	const FLUTTER_RATE: f64 = 8.0;
+
+	/// Drifts by up to `wow_depth` and `flutter_depth` frames.
+	pub fn new (wow_depth: f64, flutter_depth: f64, seed: u32, sample_rate: usize) -> Self {
+		WowFlutter {
+			wow: SmoothNoise::new(Self::WOW_RATE, seed, sample_rate),
+			flutter: SmoothNoise::new(Self::FLUTTER_RATE, seed.rotate_left(16), sample_rate),
+			wow_depth,
+			flutter_depth,
+		}
+	}
+
+	/// Keeps the rates at `sample_rate`, the depths grow by `ratio` to stay as long in time.
+	fn set_sample_rate (&mut self, sample_rate: usize, ratio: f64) {
+		self.wow.set_sample_rate(sample_rate);
+		self.flutter.set_sample_rate(sample_rate);
+		self.wow_depth *= ratio;
+		self.flutter_depth *= ratio;
+	}
+
+	/// Advances by one frame and returns the drift in frames, always making the delay longer.
+	fn next (&mut self) -> f64 {
+		self.wow.next() * self.wow_depth + self.flutter.next() * self.flutter_depth
+	}
+}
+
+/// Levels below about -400 dB are flushed to zero where a signal recirculates.
+/// Inaudible anyway, but left alone, decaying feedback tails and filter states end
+/// up as denormals, which are many times slower to compute on most cpus.
+const DENORMAL_THRESHOLD: Sample = 1e-20;
+
+fn flush_denormal (sample: Sample) -> Sample {
+	if sample.abs() < DENORMAL_THRESHOLD {
+		0.0
+	} else {
+		sample
+	}
+}
+
+/// A one pole filter, used as a low-pass directly
+/// or as a high-pass by subtracting the low-passed signal.
+struct OnePole {
+	cutoff: f64,
+	coefficient: Sample,
+	state: Sample,
+}
+
+impl OnePole {
+	fn new (cutoff: f64, sample_rate: usize) -> Self {
+		let mut filter = OnePole { cutoff, coefficient: 0.0, state: 0.0 };
+		filter.set_sample_rate(sample_rate);
+		filter
+	}
+
+	fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.coefficient = (1.0 - (-std::f64::consts::TAU * self.cutoff / sample_rate as f64).exp()) as Sample;
+	}
+
+	fn lowpass (&mut self, input: Sample) -> Sample {
+		self.state = flush_denormal(self.state + self.coefficient * (input - self.state));
+		self.state
+	}
+
+	fn highpass (&mut self, input: Sample) -> Sample {
+		input - self.lowpass(input)
+	}
+}
+
+/// A parameter gliding towards its target with a one pole smoother,
+/// so changes at runtime don't cause zipper noise.
+#[derive(Clone, Copy, Debug)]
+struct Smoothed {
+	value: Sample,
+	target: Sample,
+	coefficient: Sample,
+}
+
+impl Smoothed {
+	/// Starts out at `value`, jumping to new targets until a smoothing time is set.
+	fn new (value: f32) -> Self {
+		Smoothed {
+			value: value as Sample,
+			target: value as Sample,
+			coefficient: 1.0,
+		}
+	}
+
+	fn set_coefficient (&mut self, coefficient: f32) {
+		self.coefficient = coefficient as Sample;
+	}
+
+	fn set (&mut self, target: f32) {
+		self.target = target as Sample;
+	}
+
+	/// Advances the smoother by one frame and returns the current value.
+	fn next (&mut self) -> Sample {
+		self.value += self.coefficient * (self.target - self.value);
+		if (self.target - self.value).abs() < DENORMAL_THRESHOLD {
+			self.value = self.target;
+		}
+		self.value
+	}
+}
+
+/// Shifts the pitch of a signal with two read positions sweeping through a short
+/// delay, each faded in and out with a triangular window so they overlap to unity.
+struct PitchShifter {
+	buffer: Vec<Sample>,
+	write: usize,
+	/// length of the sweep in frames
+	window: f64,
+	/// position within the sweep of the first read position, 0.0 - 1.0
+	phase: f64,
+	/// change of `phase` per frame
+	increment: f64,
+	/// speed of the read positions relative to the input
+	ratio: f64,
+}
+
+impl PitchShifter {
+	/// Length of the sweep in milliseconds, long enough for low notes, short enough to not smear.
+	const WINDOW_MS: f64 = 50.0;
+
+	fn new (semitones: f64, sample_rate: usize) -> Self {
+		let window = millis_to_frames(Self::WINDOW_MS, sample_rate).max(2.0);
+		let ratio = 2f64.powf(semitones / 12.0);
+		PitchShifter {
+			buffer: vec![0.0; window.ceil() as usize + 2],
+			write: 0,
+			window,
+			phase: 0.0,
+			// a read position moving through the delay at `ratio` times the speed
+			increment: (1.0 - ratio) / window,
+			ratio,
+		}
+	}
+
+	/// Keeps the sweep as long in time at `sample_rate`, as far as the buffer reaches.
+	fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.window = millis_to_frames(Self::WINDOW_MS, sample_rate).max(2.0).min((self.buffer.len() - 2) as f64);
+		self.increment = (1.0 - self.ratio) / self.window;
+	}
+
+	fn process (&mut self, input: Sample) -> Sample {
+		let len = self.buffer.len();
+		self.write = (self.write + 1) % len;
+		self.buffer[self.write] = input;
+
+		let mut out = 0.0;
+		for phase in [self.phase, (self.phase + 0.5).fract()] {
+			let delay = phase * self.window;
+			let whole = delay.floor();
+			let frac = (delay - whole) as Sample;
+			let newer = (self.write + len - whole as usize) % len;
+			let older = (newer + len - 1) % len;
+			let gain = 1.0 - (2.0 * phase as Sample - 1.0).abs();
+			out += (self.buffer[newer] * (1.0 - frac) + self.buffer[older] * frac) * gain;
+		}
+		self.phase = (self.phase + self.increment).rem_euclid(1.0);
+		out
+	}
+}
+
+/// A second order filter in direct form I.
+#[derive(Clone, Copy)]
+struct Biquad {
+	b: [Sample; 3],
+	a: [Sample; 2],
+	inputs: [Sample; 2],
+	outputs: [Sample; 2],
+}
+
+impl Biquad {
+	/// Butterworth low-pass or high-pass at `cutoff`.
+	fn butterworth (cutoff: f64, highpass: bool, sample_rate: usize) -> Self {
+		let omega = std::f64::consts::TAU * cutoff / sample_rate as f64;
+		let alpha = omega.sin() * std::f64::consts::FRAC_1_SQRT_2;
+		let cos = omega.cos();
+		let a0 = 1.0 + alpha;
+		let (b0, b1) = if highpass { ((1.0 + cos) / 2.0, -(1.0 + cos)) } else { ((1.0 - cos) / 2.0, 1.0 - cos) };
+		Biquad {
+			b: [(b0 / a0) as Sample, (b1 / a0) as Sample, (b0 / a0) as Sample],
+			a: [(-2.0 * cos / a0) as Sample, ((1.0 - alpha) / a0) as Sample],
+			inputs: [0.0; 2],
+			outputs: [0.0; 2],
+		}
+	}
+
+	fn process (&mut self, input: Sample) -> Sample {
+		let output = self.b[0] * input + self.b[1] * self.inputs[0] + self.b[2] * self.inputs[1]
+			- self.a[0] * self.outputs[0] - self.a[1] * self.outputs[1];
+		self.inputs = [input, self.inputs[0]];
+		self.outputs = [output, self.outputs[0]];
+		output
+	}
+}
+
+/// A fourth order Linkwitz-Riley crossover, low and high band sum up to an all-pass.
+#[derive(Clone, Copy)]
+struct Crossover {
+	lowpass: [Biquad; 2],
+	highpass: [Biquad; 2],
+}
+
+impl Crossover {
+	fn new (frequency: f64, sample_rate: usize) -> Self {
+		Crossover {
+			lowpass: [Biquad::butterworth(frequency, false, sample_rate); 2],
+			highpass: [Biquad::butterworth(frequency, true, sample_rate); 2],
+		}
+	}
+
+	/// Returns the low and the high band of `input`.
+	fn split (&mut self, input: Sample) -> (Sample, Sample) {
+		let [low_1, low_2] = &mut self.lowpass;
+		let [high_1, high_2] = &mut self.highpass;
+		(low_2.process(low_1.process(input)), high_2.process(high_1.process(input)))
+	}
+}
+
+/// Splits a signal into bands and delays each of them by its own amount.
+pub struct BandSplitter {
+	/// frequencies of the crossovers, lowest first
+	frequencies: Vec<f64>,
+	/// crossovers between the bands, lowest first
+	crossovers: Vec<Crossover>,
+	/// per band copies of the crossovers above it, giving
+	/// the lower bands the same phase shift as the higher ones
+	allpasses: Vec<Vec<Crossover>>,
+	/// ring buffer of each band, one frame longer than its delay at first
+	buffers: Vec<Vec<Sample>>,
+	/// delay of each band in frames
+	delays: Vec<usize>,
+	write: usize,
+}
+
+impl BandSplitter {
+	/// Creates `delays.len()` bands, separated at the ascending `frequencies`.
+	pub fn new (frequencies: &[f64], delays: &[usize], sample_rate: usize) -> Self {
+		let crossovers: Vec<Crossover> = frequencies.iter().map(|&frequency| Crossover::new(frequency, sample_rate)).collect();
+		BandSplitter {
+			frequencies: frequencies.to_vec(),
+			allpasses: (0..delays.len()).map(|band| crossovers.iter().skip(band + 1).copied().collect()).collect(),
+			crossovers,
+			buffers: delays.iter().map(|&delay| vec![0.0; delay + 1]).collect(),
+			delays: delays.to_vec(),
+			write: 0,
+		}
+	}
+
+	/// Moves the crossovers to the same frequencies at `sample_rate` and makes the delays
+	/// `ratio` times as long, as far as the buffers reach. Returns false if a delay was cut.
+	fn set_sample_rate (&mut self, sample_rate: usize, ratio: f64) -> bool {
+		for (crossover, &frequency) in self.crossovers.iter_mut().zip(&self.frequencies) {
+			*crossover = Crossover::new(frequency, sample_rate);
+		}
+		for (band, allpasses) in self.allpasses.iter_mut().enumerate() {
+			for (allpass, &frequency) in allpasses.iter_mut().zip(self.frequencies.iter().skip(band + 1)) {
+				*allpass = Crossover::new(frequency, sample_rate);
+			}
+		}
+		let mut fit = true;
+		for (delay, buffer) in self.delays.iter_mut().zip(&self.buffers) {
+			let scaled = (*delay as f64 * ratio).round() as usize;
+			fit &= scaled < buffer.len();
+			*delay = scaled.min(buffer.len() - 1);
+		}
+		fit
+	}
+
+	fn process (&mut self, input: Sample) -> Sample {
+		self.write += 1;
+		let mut rest = input;
+		let mut out = 0.0;
+		for band in 0..self.buffers.len() {
+			let mut signal = match self.crossovers.get_mut(band) {
+				Some(crossover) => {
+					let (low, high) = crossover.split(rest);
+					rest = high;
+					low
+				}
+				None => rest,
+			};
+			for allpass in &mut self.allpasses[band] {
+				let (low, high) = allpass.split(signal);
+				signal = low + high;
+			}
+			let buffer = &mut self.buffers[band];
+			let len = buffer.len();
+			buffer[self.write % len] = signal;
+			out += buffer[(self.write + len - self.delays[band]) % len];
+		}
+		out
+	}
+}
+
+/// Reduces the bit depth and the sample rate of a signal by quantizing and holding it.
+struct Crusher {
+	/// quantization step, 0.0 keeps the full resolution
+	step: Sample,
+	/// frames held per second, `None` keeps the full rate
+	rate: Option<f32>,
+	/// share of a held frame that passes with each frame
+	increment: f64,
+	phase: f64,
+	held: Sample,
+}
+
+impl Crusher {
+	fn new (bits: Option<u32>, rate: Option<f32>, sample_rate: usize) -> Self {
+		let mut crusher = Crusher {
+			step: bits.map_or(0.0, |bits| 1.0 / (1u32 << (bits - 1)) as Sample),
+			rate,
+			increment: 1.0,
+			// the first frame is taken right away
+			phase: 1.0,
+			held: 0.0,
+		};
+		crusher.set_sample_rate(sample_rate);
+		crusher
+	}
+
+	fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.increment = self.rate.map_or(1.0, |rate| (rate as f64 / sample_rate as f64).min(1.0));
+	}
+
+	fn process (&mut self, input: Sample) -> Sample {
+		if self.phase >= 1.0 {
+			self.phase -= 1.0;
+			self.held = if self.step > 0.0 { (input / self.step).round() * self.step } else { input };
+		}
+		self.phase += self.increment;
+		self.held
+	}
+}
+
+/// Turns the feedback path down whenever the recirculating signal gets louder than a ceiling.
+struct FeedbackClamp {
+	ceiling: Sample,
+	/// peak level of the feedback signal
+	level: Sample,
+	release: Sample,
+}
+
+impl FeedbackClamp {
+	const RELEASE_MS: f64 = 200.0;
+
+	fn new (ceiling_db: f32, sample_rate: usize) -> Self {
+		FeedbackClamp {
+			ceiling: db_to_gain(ceiling_db) as Sample,
+			level: 0.0,
+			release: smoothing_coefficient(Self::RELEASE_MS, sample_rate) as Sample,
+		}
+	}
+
+	fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.release = smoothing_coefficient(Self::RELEASE_MS, sample_rate) as Sample;
+	}
+
+	fn process (&mut self, input: Sample) -> Sample {
+		let peak = input.abs();
+		// the level jumps up right away, so not a single frame gets past the ceiling
+		if peak > self.level {
+			self.level = peak;
+		} else {
+			self.level += self.release * (peak - self.level);
+		}
+		if self.level > self.ceiling {
+			input * self.ceiling / self.level
+		} else {
+			input
+		}
+	}
+}
+
+/// Limits both outputs together, bending levels above a knee smoothly towards the ceiling.
+/// Reacts instantly to peaks without looking ahead and recovers slowly.
+pub struct SoftLimiter {
+	ceiling: Sample,
+	/// level where the gain reduction starts, 6 dB below the ceiling
+	knee: Sample,
+	/// peak level of the outputs
+	level: Sample,
+	release: Sample,
+}
+
+impl SoftLimiter {
+	const RELEASE_MS: f64 = 100.0;
+
+	/// Limits to `ceiling_db` dBFS.
+	pub fn new (ceiling_db: f32, sample_rate: usize) -> Self {
+		let ceiling = db_to_gain(ceiling_db) as Sample;
+		SoftLimiter {
+			ceiling,
+			knee: ceiling / 2.0,
+			level: 0.0,
+			release: smoothing_coefficient(Self::RELEASE_MS, sample_rate) as Sample,
+		}
+	}
+
+	/// Keeps the release as long in time at `sample_rate`.
+	pub fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.release = smoothing_coefficient(Self::RELEASE_MS, sample_rate) as Sample;
+	}
+
+	/// Follows the louder of both outputs by one frame and returns the gain for them.
+	fn next (&mut self, peak: Sample) -> Sample {
+		if peak > self.level {
+			self.level = peak;
+		} else {
+			self.level += self.release * (peak - self.level);
+		}
+		if self.level <= self.knee {
+			return 1.0;
+		}
+		// leaves the knee at unity slope and approaches the ceiling without reaching it
+		let range = self.ceiling - self.knee;
+		let limited = self.knee + range * ((self.level - self.knee) / range).tanh();
+		limited / self.level
+	}
+}
+
+/// Follows the level of a sidechain signal and derives
+/// the gain for the delayed signal from it.
+pub struct Ducker {
+	/// level above which the delayed signal is ducked
+	threshold: f32,
+	/// gain of the delayed signal while ducked
+	ducked_gain: f32,
+	attack_ms: f64,
+	release_ms: f64,
+	attack: f32,
+	release: f32,
+	level: f32,
+	gain: f32,
+}
+
+impl Ducker {
+	/// Ducks by `depth_db` while the sidechain is above `threshold_db`.
+	pub fn new (threshold_db: f32, depth_db: f32, attack_ms: f64, release_ms: f64, sample_rate: usize) -> Self {
+		Ducker {
+			threshold: db_to_gain(threshold_db),
+			ducked_gain: db_to_gain(-depth_db.abs()),
+			attack_ms,
+			release_ms,
+			attack: smoothing_coefficient(attack_ms, sample_rate),
+			release: smoothing_coefficient(release_ms, sample_rate),
+			level: 0.0,
+			gain: 1.0,
+		}
+	}
+
+	/// Keeps attack and release as long in time at `sample_rate`.
+	pub fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.attack = smoothing_coefficient(self.attack_ms, sample_rate);
+		self.release = smoothing_coefficient(self.release_ms, sample_rate);
+	}
+
+	/// Fills `gains` with the gain for each frame of the sidechain buffer.
+	pub fn process (&mut self, sidechain: &[f32], gains: &mut [f32]) {
+		for (input, gain) in sidechain.iter().zip(gains.iter_mut()) {
+			let input = input.abs();
+			let coefficient = if input > self.level { self.attack } else { self.release };
+			self.level += coefficient * (input - self.level);
+
+			let target = if self.level > self.threshold { self.ducked_gain } else { 1.0 };
+			let coefficient = if target < self.gain { self.attack } else { self.release };
+			self.gain += coefficient * (target - self.gain);
+			*gain = self.gain;
+		}
+	}
+}
+
+/// Keeps quiet input like bleed or the noise floor out of the delay line.
+pub struct Gate {
+	/// level below which the gate closes
+	threshold: Sample,
+	attack_ms: f64,
+	release_ms: f64,
+	attack: Sample,
+	release: Sample,
+	level: Sample,
+	gain: Sample,
+}
+
+impl Gate {
+	/// Opens while the input is above `threshold_db`.
+	pub fn new (threshold_db: f32, attack_ms: f64, release_ms: f64, sample_rate: usize) -> Self {
+		Gate {
+			threshold: db_to_gain(threshold_db) as Sample,
+			attack_ms,
+			release_ms,
+			attack: smoothing_coefficient(attack_ms, sample_rate) as Sample,
+			release: smoothing_coefficient(release_ms, sample_rate) as Sample,
+			level: 0.0,
+			gain: 0.0,
+		}
+	}
+
+	/// Keeps attack and release as long in time at `sample_rate`.
+	pub fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.attack = smoothing_coefficient(self.attack_ms, sample_rate) as Sample;
+		self.release = smoothing_coefficient(self.release_ms, sample_rate) as Sample;
+	}
+
+	/// Follows the level of `input` by one frame and returns the gain of the gate.
+	fn next (&mut self, input: Sample) -> Sample {
+		let input = input.abs();
+		let coefficient = if input > self.level { self.attack } else { self.release };
+		self.level += coefficient * (input - self.level);
+
+		let target = if self.level > self.threshold { 1.0 } else { 0.0 };
+		let coefficient = if target > self.gain { self.attack } else { self.release };
+		self.gain += coefficient * (target - self.gain);
+		self.gain
+	}
+}
+
+/// Fades the delayed signal out on every transient of the input and back in
+/// afterwards, so the echoes swell in behind the notes.
+pub struct Swell {
+	/// level of the input following quickly and slowly
+	fast: Sample,
+	slow: Sample,
+	fast_coefficient: Sample,
+	slow_coefficient: Sample,
+	gain: Sample,
+	/// change of the gain per frame while fading out and in
+	fall: Sample,
+	rise: Sample,
+	falling: bool,
+	swell_ms: f64,
+}
+
+impl Swell {
+	/// a transient is the fast level rising this far above the slow one
+	const ONSET_RATIO: Sample = 2.0;
+	/// level below which nothing counts as a transient, about -60 dBFS
+	const FLOOR: Sample = 0.001;
+
+	/// Fades back in over `swell_ms` after each transient.
+	pub fn new (swell_ms: f64, sample_rate: usize) -> Self {
+		let mut swell = Swell {
+			fast: 0.0,
+			slow: 0.0,
+			fast_coefficient: 1.0,
+			slow_coefficient: 1.0,
+			gain: 1.0,
+			fall: 1.0,
+			rise: 1.0,
+			falling: false,
+			swell_ms,
+		};
+		swell.set_sample_rate(sample_rate);
+		swell
+	}
+
+	/// Keeps the level followers and the fades as long in time at `sample_rate`.
+	pub fn set_sample_rate (&mut self, sample_rate: usize) {
+		self.fast_coefficient = smoothing_coefficient(1.0, sample_rate) as Sample;
+		self.slow_coefficient = smoothing_coefficient(50.0, sample_rate) as Sample;
+		self.fall = 1.0 / millis_to_frames(2.0, sample_rate).max(1.0) as Sample;
+		self.rise = 1.0 / millis_to_frames(self.swell_ms, sample_rate).max(1.0) as Sample;
+	}
+
+	/// Follows the level of `input` by one frame and returns the gain of the delayed signal.
+	fn next (&mut self, input: Sample) -> Sample {
+		let input = input.abs();
+		self.fast += self.fast_coefficient * (input - self.fast);
+		self.slow += self.slow_coefficient * (input - self.slow);
+		if self.fast > self.slow * Self::ONSET_RATIO && self.fast > Self::FLOOR {
+			self.falling = true;
+		}
+		if self.falling {
+			self.gain -= self.fall;
+			if self.gain <= 0.0 {
+				self.gain = 0.0;
+				self.falling = false;
+			}
+		} else {
+			self.gain = (self.gain + self.rise).min(1.0);
+		}
+		self.gain
+	}
+}
+
+/// Storage of a delay line, in memory or in a file for delays too long to keep in RAM.
+enum Buffer {
+	Memory(Vec<Sample>),
+	Mapped(MappedBuffer),
+}
+
+impl std::ops::Deref for Buffer {
+	type Target = [Sample];
+
+	fn deref (&self) -> &[Sample] {
+		match self {
+			Buffer::Memory(frames) => frames,
+			// the mapping is valid and owned by the buffer until it is dropped
+			Buffer::Mapped(mapped) => unsafe { std::slice::from_raw_parts(mapped.frames, mapped.len) },
+		}
+	}
+}
+
+impl std::ops::DerefMut for Buffer {
+	fn deref_mut (&mut self) -> &mut [Sample] {
+		match self {
+			Buffer::Memory(frames) => frames,
+			Buffer::Mapped(mapped) => unsafe { std::slice::from_raw_parts_mut(mapped.frames, mapped.len) },
+		}
+	}
+}
+
+/// Frames mapped from a file, which the kernel reads and writes behind the scenes.
+/// A [`Pager`] makes sure that never happens within the process callback.
+struct MappedBuffer {
+	file: std::fs::File,
+	frames: *mut Sample,
+	len: usize,
+	positions: Arc<PagedPositions>,
+}
+
+// the mapping is only ever accessed through the line owning it
+unsafe impl Send for MappedBuffer {}
+
+impl MappedBuffer {
+	/// Maps the first `len` frames of `file`, growing it with zeros if needed.
+	fn map (file: &std::fs::File, len: usize) -> std::io::Result<*mut Sample> {
+		let bytes = len * std::mem::size_of::<Sample>();
+		file.set_len(bytes as u64)?;
+		let frames = unsafe {
+			libc::mmap(std::ptr::null_mut(), bytes, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, file.as_raw_fd(), 0)
+		};
+		if frames == libc::MAP_FAILED {
+			return Err(std::io::Error::last_os_error());
+		}
+		Ok(frames as *mut Sample)
+	}
+
+	fn unmap (&mut self) {
+		unsafe {
+			libc::munmap(self.frames as *mut libc::c_void, self.len * std::mem::size_of::<Sample>());
+		}
+	}
+
+	/// Maps `len` frames in place of the current ones, which stay in the file.
+	fn remap (&mut self, len: usize) -> std::io::Result<()> {
+		let frames = MappedBuffer::map(&self.file, len)?;
+		self.unmap();
+		self.frames = frames;
+		self.len = len;
+		self.positions.frames.store(frames, Ordering::Relaxed);
+		self.positions.len.store(len, Ordering::Relaxed);
+		Ok(())
+	}
+}
+
+impl Drop for MappedBuffer {
+	fn drop (&mut self) {
+		self.unmap();
+	}
+}
+
+/// Where a mapped line is written and read, published for its pager.
+struct PagedPositions {
+	frames: AtomicPtr<Sample>,
+	len: AtomicUsize,
+	write: AtomicUsize,
+	/// one per tap
+	reads: Vec<AtomicUsize>,
+}
+
+impl PagedPositions {
+	fn new (frames: *mut Sample, len: usize, write: usize, taps: usize) -> Arc<Self> {
+		Arc::new(PagedPositions {
+			frames: AtomicPtr::new(frames),
+			len: AtomicUsize::new(len),
+			write: AtomicUsize::new(write),
+			reads: (0..taps).map(|_| AtomicUsize::new(write)).collect(),
+		})
+	}
+}
+
+/// Pages in what a line in a file is about to write and read, see [`DelayLine::set_buffer_file`].
+pub struct Pager {
+	positions: Arc<PagedPositions>,
+}
+
+impl Pager {
+	/// Keeps the next `lookahead` frames after the write and every read position
+	/// in memory. Blocks until the line is dropped, so it has to run on its own thread.
+	pub fn run (self, lookahead: usize) {
+		let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as usize;
+		let size = std::mem::size_of::<Sample>();
+		while Arc::strong_count(&self.positions) > 1 {
+			let frames = self.positions.frames.load(Ordering::Relaxed) as usize;
+			let len = self.positions.len.load(Ordering::Relaxed);
+			let reads = self.positions.reads.iter().map(|read| read.load(Ordering::Relaxed));
+			for start in std::iter::once(self.positions.write.load(Ordering::Relaxed)).chain(reads) {
+				let start = start % len;
+				let end = start + lookahead.min(len);
+				// the part past the end of the ring continues at its start
+				for (from, to) in [(start, end.min(len)), (0, end.saturating_sub(len))] {
+					if from < to {
+						let address = (frames + from * size) / page * page;
+						let bytes = frames + to * size - address;
+						// only a hint, a failure just means the callback may wait for the disk
+						unsafe {
+							libc::madvise(address as *mut libc::c_void, bytes, libc::MADV_WILLNEED);
+						}
+					}
+				}
+			}
+			std::thread::sleep(std::time::Duration::from_millis(10));
+		}
+	}
+}
+
+/// A single channel delay line on top of a ring buffer.
+/// Each tap reads the line a possibly fractional number of frames
+/// behind the write position, which is resolved by linear interpolation.
+pub struct DelayLine {
+	buffer: Buffer,
+	/// index of the most recently written frame
+	write: usize,
+	taps: Vec<Tap>,
+	fade_frames: usize,
+	change_mode: ChangeMode,
+	interpolation: Interpolation,
+	sinc: Option<SincTable>,
+	/// amount of the output that is written back into the line
+	feedback: Smoothed,
+	/// amount of the opposite channel's output written into the line
+	cross_feedback: Smoothed,
+	feedback_lowpass: Option<OnePole>,
+	feedback_highpass: Option<OnePole>,
+	/// filters shaping the delayed signal on the output only, the repeats stay untouched
+	wet_lowpass: Option<OnePole>,
+	wet_highpass: Option<OnePole>,
+	/// drive of the soft clipper in the feedback path
+	saturation: Option<Sample>,
+	/// pitch shift applied to each repeat
+	shimmer: Option<PitchShifter>,
+	/// limiter at the end of the feedback path
+	feedback_clamp: Option<FeedbackClamp>,
+	/// lo-fi storage of everything written into the line
+	crusher: Option<Crusher>,
+	/// balance between the input and the delayed signal on the output
+	mix: Smoothed,
+	/// play each window of `delay` frames backwards
+	reverse: bool,
+	/// length of the grains played back in shuffled order, `None` plays the line as recorded
+	grain_frames: Option<usize>,
+	/// xorshift state picking the grains
+	seed: u32,
+	lfo: Lfo,
+	/// maximum offset of the read positions in frames
+	mod_depth: f64,
+	/// random timing of each repeat
+	humanize: Option<Humanize>,
+	wow_flutter: Option<WowFlutter>,
+	/// frames the read positions move by per unit of control voltage, and at none
+	cv_depth: f64,
+	cv_offset: f64,
+	/// loop the current contents instead of recording new input
+	frozen: bool,
+	/// frames added to the delay of every tap
+	offset: f64,
+	/// -1.0 to invert the delayed signal on the output
+	polarity: Sample,
+	/// high-pass removing dc offsets before they recirculate
+	dc_blocker: Option<OnePole>,
+	/// per band delays in front of the line
+	bands: Option<BandSplitter>,
+}
+
+impl DelayLine {
+	/// Creates a delay line without any taps, `fade_frames` is the length
+	/// of the crossfade or glide applied when a delay is changed later on.
+	pub fn new (fade_frames: usize) -> Self {
+		DelayLine {
+			buffer: Buffer::Memory(vec![0.0; 2]),
+			write: 0,
+			taps: vec!(),
+			fade_frames,
+			change_mode: ChangeMode::Crossfade,
+			interpolation: Interpolation::Linear,
+			sinc: None,
+			feedback: Smoothed::new(0.0),
+			cross_feedback: Smoothed::new(0.0),
+			feedback_lowpass: None,
+			feedback_highpass: None,
+			wet_lowpass: None,
+			wet_highpass: None,
+			saturation: None,
+			shimmer: None,
+			feedback_clamp: None,
+			crusher: None,
+			mix: Smoothed::new(1.0),
+			reverse: false,
+			grain_frames: None,
+			seed: 0x2545_f491,
+			lfo: Lfo::new(0.0, LfoShape::Sine, 0.0, 1),
+			mod_depth: 0.0,
+			humanize: None,
+			wow_flutter: None,
+			cv_depth: 0.0,
+			cv_offset: 0.0,
+			frozen: false,
+			offset: 0.0,
+			polarity: 1.0,
+			dc_blocker: None,
+			bands: None,
+		}
+	}
+
+	/// Adds a read position `delay` frames behind the input, `pan` places
+	/// it between the left (-1.0) and the right (1.0) output.
+	pub fn add_tap (&mut self, delay: f64, gain: f32, pan: f32) {
+		// a negative delay can go as far as the offset
+		let delay = delay.max(-self.offset);
+		self.reserve(delay);
+		// constant power pan law
+		let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+		self.taps.push(Tap {
+			delay,
+			gain: Smoothed::new(gain),
+			pan_gains: [angle.cos() as Sample, angle.sin() as Sample],
+			previous_delay: delay,
+			fade_remaining: 0,
+			fade_length: 0,
+			window_pos: 0,
+			grain_offset: 0,
+			interpolation_state: [0.0; 2],
+		});
+	}
+
+	/// Sets the coefficient with which feedback, mix and tap gains follow changes.
+	pub fn set_smoothing (&mut self, coefficient: f32) {
+		self.feedback.set_coefficient(coefficient);
+		self.cross_feedback.set_coefficient(coefficient);
+		self.mix.set_coefficient(coefficient);
+		for tap in &mut self.taps {
+			tap.gain.set_coefficient(coefficient);
+		}
+	}
+
+	/// Sets the linear gain of a tap.
+	pub fn set_tap_gain (&mut self, index: usize, gain: f32) {
+		if let Some(tap) = self.taps.get_mut(index) {
+			tap.gain.set(gain);
+		}
+	}
+
+	/// Sets how much of the delayed signal is written back, 0.0 - 1.0.
+	pub fn set_feedback (&mut self, feedback: f32) {
+		self.feedback.set(feedback);
+	}
+
+	/// Sets how much of the opposite channel's delayed signal is written into the line.
+	pub fn set_cross_feedback (&mut self, cross_feedback: f32) {
+		self.cross_feedback.set(cross_feedback);
+	}
+
+	/// Sets the cutoffs of the filters in the feedback path, `None` disables a filter.
+	pub fn set_feedback_filters (&mut self, lowpass: Option<f64>, highpass: Option<f64>, sample_rate: usize) {
+		self.feedback_lowpass = lowpass.map(|cutoff| OnePole::new(cutoff, sample_rate));
+		self.feedback_highpass = highpass.map(|cutoff| OnePole::new(cutoff, sample_rate));
+	}
+
+	/// Sets the cutoffs of the filters on the output, `None` disables a filter.
+	pub fn set_wet_filters (&mut self, lowpass: Option<f64>, highpass: Option<f64>, sample_rate: usize) {
+		self.wet_lowpass = lowpass.map(|cutoff| OnePole::new(cutoff, sample_rate));
+		self.wet_highpass = highpass.map(|cutoff| OnePole::new(cutoff, sample_rate));
+	}
+
+	/// Stores the line at a lower bit depth and sample rate, `None` for both keeps it clean.
+	pub fn set_crusher (&mut self, bits: Option<u32>, rate: Option<f32>, sample_rate: usize) {
+		self.crusher = if bits.is_some() || rate.is_some() { Some(Crusher::new(bits, rate, sample_rate)) } else { None };
+	}
+
+	/// Limits the feedback path to `ceiling_db` dBFS.
+	pub fn set_feedback_ceiling (&mut self, ceiling_db: f32, sample_rate: usize) {
+		self.feedback_clamp = Some(FeedbackClamp::new(ceiling_db, sample_rate));
+	}
+
+	/// Shifts every repeat by `semitones`, `None` disables the pitch shifter.
+	pub fn set_shimmer (&mut self, semitones: Option<f64>, sample_rate: usize) {
+		self.shimmer = semitones.map(|semitones| PitchShifter::new(semitones, sample_rate));
+	}
+
+	/// Soft clips the feedback path with `drive`, `None` keeps it clean.
+	pub fn set_saturation (&mut self, drive: Option<f32>) {
+		self.saturation = drive.map(|drive| drive as Sample);
+	}
+
+	/// Sets the balance between the input (0.0) and the delayed signal (1.0).
+	pub fn set_mix (&mut self, mix: f32) {
+		self.mix.set(mix);
+	}
+
+	/// Delays each repeat by a random amount of up to `depth` frames, `None` keeps the timing exact.
+	pub fn set_humanize (&mut self, depth: Option<f64>, seed: u32) {
+		self.humanize = depth.map(|depth| Humanize::new(depth, seed));
+		for index in 0..self.taps.len() {
+			self.reserve(self.taps[index].delay);
+		}
+	}
+
+	/// Lets all read positions drift randomly like on a tape machine.
+	pub fn set_wow_flutter (&mut self, wow_flutter: Option<WowFlutter>) {
+		self.wow_flutter = wow_flutter;
+		for index in 0..self.taps.len() {
+			self.reserve(self.taps[index].delay);
+		}
+	}
+
+	/// Largest offset any modulation can add to the read positions.
+	fn modulation_depth (&self) -> f64 {
+		self.mod_depth
+			+ self.humanize.as_ref().map_or(0.0, |humanize| humanize.depth)
+			+ self.wow_flutter.as_ref().map_or(0.0, |wow_flutter| wow_flutter.wow_depth + wow_flutter.flutter_depth)
+			+ self.cv_offset.abs() + self.cv_depth.abs()
+	}
+
+	/// Modulates all read positions by up to `depth` frames following the lfo.
+	pub fn set_modulation (&mut self, lfo: Lfo, depth: f64) {
+		self.lfo = lfo;
+		self.mod_depth = depth;
+		for index in 0..self.taps.len() {
+			self.reserve(self.taps[index].delay);
+		}
+	}
+
+	/// Moves all read positions by `offset` plus `depth` frames per unit of the control voltage
+	/// given to `StereoDelay::process`, which is clamped to ±1.
+	pub fn set_cv (&mut self, depth: f64, offset: f64) {
+		self.cv_depth = depth;
+		self.cv_offset = offset;
+		for index in 0..self.taps.len() {
+			self.reserve(self.taps[index].delay);
+		}
+	}
+
+	/// Delays every tap by an additional `offset` frames.
+	pub fn set_offset (&mut self, offset: f64) {
+		self.offset = offset;
+		for index in 0..self.taps.len() {
+			self.reserve(self.taps[index].delay);
+		}
+	}
+
+	/// Sets how fractional delays are read.
+	pub fn set_interpolation (&mut self, interpolation: Interpolation) {
+		self.interpolation = interpolation;
+		self.sinc = match interpolation {
+			Interpolation::Sinc(points) => Some(SincTable::new(points)),
+			_ => None,
+		};
+		for index in 0..self.taps.len() {
+			self.reserve(self.taps[index].delay);
+		}
+	}
+
+	/// Inserts a high-pass at 5 Hz in front of the line.
+	pub fn set_dc_blocking (&mut self, enabled: bool, sample_rate: usize) {
+		self.dc_blocker = if enabled { Some(OnePole::new(5.0, sample_rate)) } else { None };
+	}
+
+	/// Delays the bands of the input separately before they enter the line.
+	pub fn set_bands (&mut self, bands: Option<BandSplitter>) {
+		self.bands = bands;
+	}
+
+	/// Inverts the polarity of the delayed signal on the output.
+	pub fn set_inverted (&mut self, inverted: bool) {
+		self.polarity = if inverted { -1.0 } else { 1.0 };
+	}
+
+	/// Loops the current contents instead of recording new input.
+	pub fn set_frozen (&mut self, frozen: bool) {
+		self.frozen = frozen;
+	}
+
+	/// Silences everything recorded so far, the tails included. This goes through the whole
+	/// buffer, which takes a while for delays of minutes.
+	pub fn clear (&mut self) {
+		self.buffer.fill(0.0);
+		let mut filters = [&mut self.feedback_lowpass, &mut self.feedback_highpass, &mut self.wet_lowpass, &mut self.wet_highpass, &mut self.dc_blocker];
+		for filter in filters.iter_mut().filter_map(|filter| filter.as_mut()) {
+			filter.state = 0.0;
+		}
+		if let Some(shimmer) = &mut self.shimmer {
+			shimmer.buffer.fill(0.0);
+		}
+		for buffer in self.bands.iter_mut().flat_map(|bands| &mut bands.buffers) {
+			buffer.fill(0.0);
+		}
+	}
+
+	/// Sets the number of frames later delay changes are crossfaded or glide over.
+	pub fn set_glide (&mut self, fade_frames: usize) {
+		self.fade_frames = fade_frames;
+	}
+
+	/// Sets whether delay changes crossfade or glide.
+	pub fn set_change_mode (&mut self, change_mode: ChangeMode) {
+		self.change_mode = change_mode;
+	}
+
+	/// Plays the line back as grains of `grain_frames` in shuffled order, `None` turns that off.
+	pub fn set_shuffle (&mut self, grain_frames: Option<usize>) {
+		self.grain_frames = grain_frames.map(|frames| frames.max(1));
+		for index in 0..self.taps.len() {
+			self.reserve(self.taps[index].delay);
+			self.taps[index].window_pos = 0;
+		}
+	}
+
+	/// Plays each window of `delay` frames backwards.
+	pub fn set_reverse (&mut self, reverse: bool) {
+		self.reverse = reverse;
+		for index in 0..self.taps.len() {
+			self.reserve(self.taps[index].delay);
+			self.taps[index].window_pos = 0;
+		}
+	}
+
+	/// Changes the delay of a tap, as far as the buffer reaches back.
+	/// The old read position is crossfaded or glides into the new one to avoid clicks.
+	pub fn set_tap_delay (&mut self, index: usize, delay: f64) {
+		// called from the process callback, so the buffer can't grow here
+		let delay = delay.min(self.capacity()).max(-self.offset);
+		let fade_frames = self.fade_frames;
+		let change_mode = self.change_mode;
+		if let Some(tap) = self.taps.get_mut(index) {
+			tap.previous_delay = match change_mode {
+				ChangeMode::Crossfade => {
+					// the old read position keeps its interpolator memory while it fades out
+					tap.interpolation_state = [0.0, tap.interpolation_state[0]];
+					tap.delay
+				}
+				// a glide that is still running continues from where it is
+				ChangeMode::Repitch => tap.gliding_delay(),
+			};
+			tap.delay = delay;
+			tap.fade_remaining = fade_frames;
+			tap.fade_length = fade_frames;
+		}
+	}
+
+	/// Grows the ring buffer if it is too short to hold a tap at `delay`.
+	/// Allocates, so it must not be called while processing, only while setting up
+	/// or from callbacks that don't run alongside the processing like jack's buffer size callback.
+	pub fn reserve (&mut self, delay: f64) {
+		let delay = delay + self.offset;
+		// a reversed window or a shuffled grain reaches back twice as far as the delay
+		let span = if self.reverse || self.grain_frames.is_some() { delay.round() * 2.0 } else { delay + self.modulation_depth() };
+		// a sinc kernel also reaches half its length past the read position
+		let span = span + self.sinc.as_ref().map_or(0, |table| table.points / 2) as f64;
+		// one extra frame for the current sample and one for the interpolation partner
+		let needed = span.ceil() as usize + 2;
+		if needed > self.buffer.len() {
+			// unroll the ring so the newest frame is last, the new space
+			// then reads as silence older than anything recorded so far.
+			let len = self.buffer.len();
+			self.buffer.rotate_left((self.write + 1) % len);
+			match &mut self.buffer {
+				Buffer::Memory(frames) => frames.resize(needed, 0.0),
+				// there is no way to report an error from here, and a line that can't grow is no use
+				Buffer::Mapped(mapped) => mapped.remap(needed).expect("failed to grow the buffer file"),
+			}
+			self.write = len - 1;
+		}
+	}
+
+	/// Moves the buffer into a file created at `path`, for delays too long to keep in RAM.
+	/// The file is removed right away and only takes up disk space as long as the line exists,
+	/// later growing extends it. Run the [`Pager`] from `pager` once all taps are added.
+	pub fn set_buffer_file (&mut self, path: &std::path::Path) -> std::io::Result<()> {
+		let file = std::fs::OpenOptions::new().read(true).write(true).create_new(true).open(path)?;
+		std::fs::remove_file(path)?;
+		let len = self.buffer.len();
+		let frames = MappedBuffer::map(&file, len)?;
+		let positions = PagedPositions::new(frames, len, self.write, 0);
+		let mut mapped = Buffer::Mapped(MappedBuffer { file, frames, len, positions });
+		mapped.copy_from_slice(&self.buffer);
+		self.buffer = mapped;
+		Ok(())
+	}
+
+	/// The pager keeping a buffer file in memory ahead of the taps there are so far,
+	/// `None` for a buffer in memory.
+	pub fn pager (&mut self) -> Option<Pager> {
+		let (taps, write) = (self.taps.len(), self.write);
+		match &mut self.buffer {
+			Buffer::Mapped(mapped) => {
+				mapped.positions = PagedPositions::new(mapped.frames, mapped.len, write, taps);
+				Some(Pager { positions: mapped.positions.clone() })
+			}
+			Buffer::Memory(_) => None,
+		}
+	}
+
+	/// Longest delay a tap can have without growing the buffer, the inverse of `reserve`.
+	pub fn capacity (&self) -> f64 {
+		let span = (self.buffer.len() - 2) as f64 - self.sinc.as_ref().map_or(0, |table| table.points / 2) as f64;
+		let delay = if self.reverse || self.grain_frames.is_some() { (span / 2.0).floor() } else { span - self.modulation_depth() };
+		delay - self.offset
+	}
+
+	/// Carries the settings over from a server running at `previous` to one at `sample_rate`:
+	/// filters and pitch shift stay at their frequencies, the lfo at its rate, and depths,
+	/// grains and the glide keep their length in time. Taps and the offset stay as they are,
+	/// `set_tap_delay` moves them. Nothing is allocated, so whatever would grow the buffer
+	/// is cut to it, the return value is false if a tap or a band delay was cut.
+	pub fn set_sample_rate (&mut self, previous: usize, sample_rate: usize) -> bool {
+		let ratio = sample_rate as f64 / previous as f64;
+		let mut filters = [&mut self.feedback_lowpass, &mut self.feedback_highpass, &mut self.wet_lowpass, &mut self.wet_highpass, &mut self.dc_blocker];
+		for filter in filters.iter_mut().filter_map(|filter| filter.as_mut()) {
+			filter.set_sample_rate(sample_rate);
+		}
+		if let Some(shifter) = &mut self.shimmer {
+			shifter.set_sample_rate(sample_rate);
+		}
+		if let Some(clamp) = &mut self.feedback_clamp {
+			clamp.set_sample_rate(sample_rate);
+		}
+		if let Some(crusher) = &mut self.crusher {
+			crusher.set_sample_rate(sample_rate);
+		}
+		self.lfo.set_sample_rate(sample_rate);
+		self.mod_depth *= ratio;
+		if let Some(humanize) = &mut self.humanize {
+			humanize.depth *= ratio;
+		}
+		if let Some(wow_flutter) = &mut self.wow_flutter {
+			wow_flutter.set_sample_rate(sample_rate, ratio);
+		}
+		self.cv_depth *= ratio;
+		self.cv_offset *= ratio;
+		self.grain_frames = self.grain_frames.map(|frames| ((frames as f64 * ratio).round() as usize).max(1));
+		self.fade_frames = (self.fade_frames as f64 * ratio).round() as usize;
+		let mut fit = self.bands.as_mut().is_none_or(|bands| bands.set_sample_rate(sample_rate, ratio));
+		// deeper modulation leaves less room for the taps
+		let capacity = self.capacity();
+		for index in 0..self.taps.len() {
+			if self.taps[index].delay > capacity {
+				self.set_tap_delay(index, capacity);
+				fit = false;
+			}
+		}
+		fit
+	}
+
+	/// Frames until the first repeat comes out, the shortest delay of the taps.
+	pub fn onset (&self) -> Option<f64> {
+		self.taps.iter().map(|tap| tap.delay + self.offset).reduce(f64::min)
+	}
+
+	/// Reads the frame `delay` frames behind the write position.
+	fn read (&self, delay: f64) -> Sample {
+		let len = self.buffer.len();
+		let whole = delay.floor();
+		let frac = (delay - whole) as Sample;
+		let newer = (self.write + len - whole as usize % len) % len;
+		let older = (newer + len - 1) % len;
+		self.buffer[newer] * (1.0 - frac) + self.buffer[older] * frac
+	}
+
+	/// Reads a tap backwards in windows as long as its delay.
+	/// The read position moves away from the write position at twice the speed,
+	/// so each window replays the frames that preceded it in reverse.
+	fn read_tap_reversed (&mut self, index: usize) -> Sample {
+		let tap = self.taps[index];
+		let window = ((tap.delay + self.offset).round() as usize).max(1);
+		let pos = tap.window_pos % window;
+		self.taps[index].window_pos = (pos + 1) % window;
+		self.read((2 * pos + 1) as f64) * self.window_envelope(pos, window)
+	}
+
+	/// Reads a tap as grains of the last `delay` frames in random order.
+	/// Each grain plays forwards from a randomly picked slice of the line.
+	fn read_tap_shuffled (&mut self, index: usize, grain: usize) -> Sample {
+		let tap = self.taps[index];
+		let delay = tap.delay + self.offset;
+		let pos = tap.window_pos % grain;
+		if pos == 0 {
+			let slices = ((delay / grain as f64) as usize).max(1);
+			self.taps[index].grain_offset = xorshift(&mut self.seed) as usize % slices * grain;
+		}
+		self.taps[index].window_pos = (pos + 1) % grain;
+		let offset = self.taps[index].grain_offset as f64;
+		self.read(delay + offset) * self.window_envelope(pos, grain)
+	}
+
+	/// Fades in and out at the edges of a window, where the read position jumps.
+	fn window_envelope (&self, pos: usize, window: usize) -> Sample {
+		let edge = self.fade_frames.min(window / 2).max(1) as Sample;
+		((pos + 1) as Sample / edge)
+			.min((window - pos) as Sample / edge)
+			.min(1.0)
+	}
+
+	/// Reads a tap offset by `modulation` frames, advancing its crossfade if one is running.
+	fn read_tap (&mut self, index: usize, modulation: f64) -> Sample {
+		if self.reverse {
+			return self.read_tap_reversed(index);
+		}
+		if let Some(grain) = self.grain_frames {
+			return self.read_tap_shuffled(index, grain);
+		}
+		let tap = self.taps[index];
+		let [mut state, mut previous_state] = tap.interpolation_state;
+		let offset = self.offset + modulation;
+		let out = if tap.fade_remaining == 0 {
+			self.interpolate(tap.delay + offset, &mut state)
+		} else {
+			self.taps[index].fade_remaining -= 1;
+			match self.change_mode {
+				ChangeMode::Crossfade => {
+					let old_gain = tap.fade_remaining as Sample / tap.fade_length as Sample;
+					self.interpolate(tap.previous_delay + offset, &mut previous_state) * old_gain
+						+ self.interpolate(tap.delay + offset, &mut state) * (1.0 - old_gain)
+				}
+				ChangeMode::Repitch => self.interpolate(tap.gliding_delay() + offset, &mut state),
+			}
+		};
+		self.taps[index].interpolation_state = [state, previous_state];
+		out
+	}
+
+	/// Reads `delay` frames behind the write position with the configured interpolation,
+	/// `state` is the memory of stateful interpolators for this read position.
+	fn interpolate (&self, delay: f64, state: &mut Sample) -> Sample {
+		let delay = delay.max(0.0);
+		match self.interpolation {
+			Interpolation::Linear => self.read(delay),
+			// keeps the fractional part of the first order thiran allpass
+			// within 0.5 - 1.5 frames, where its delay is most accurate.
+			Interpolation::Allpass if delay >= 0.5 => {
+				let whole = (delay - 0.5).floor();
+				let frac = delay - whole;
+				let coefficient = ((1.0 - frac) / (1.0 + frac)) as Sample;
+				let out = coefficient * self.read(whole) + self.read(whole + 1.0) - coefficient * *state;
+				*state = out;
+				out
+			}
+			Interpolation::Allpass => self.read(delay),
+			Interpolation::Sinc(points) => match &self.sinc {
+				// the kernel needs frames on both sides of the read position
+				Some(table) if delay >= (points / 2) as f64 => {
+					let len = self.buffer.len();
+					let whole = delay.floor();
+					let kernel = table.kernel(delay - whole);
+					let oldest = (self.write + len - (whole as usize + points / 2) % len) % len;
+					// the frames under the kernel wrap around the end of the ring at most once
+					match self.buffer[oldest..].len() {
+						after if after >= points => dot(kernel, &self.buffer[oldest..oldest + points]),
+						after => dot(&kernel[..after], &self.buffer[oldest..]) + dot(&kernel[after..], &self.buffer[..points - after]),
+					}
+				}
+				_ => self.read(delay),
+			},
+		}
+	}
+
+	/// Runs the delayed signals of this and the other channel through the feedback path.
+	fn feedback_signal (&mut self, wet: Sample, other_wet: Sample) -> Sample {
+		let mut signal = wet * self.feedback.next() + other_wet * self.cross_feedback.next();
+		if let Some(filter) = &mut self.feedback_lowpass {
+			signal = filter.lowpass(signal);
+		}
+		if let Some(filter) = &mut self.feedback_highpass {
+			signal = filter.highpass(signal);
+		}
+		if let Some(shifter) = &mut self.shimmer {
+			signal = shifter.process(signal);
+		}
+		if let Some(drive) = self.saturation {
+			// unity gain for quiet repeats, loud ones approach 1 / drive
+			signal = (signal * drive).tanh() / drive;
+		}
+		if let Some(clamp) = &mut self.feedback_clamp {
+			signal = clamp.process(signal);
+		}
+		signal
+	}
+
+	/// Advances the line by one frame, records `input` and returns the sum
+	/// of all taps along with their panned share of the left and right output.
+	/// `cv` is the control voltage of this frame.
+	fn record (&mut self, input: Sample, cv: Sample) -> (Sample, [Sample; 2]) {
+		self.write = (self.write + 1) % self.buffer.len();
+		if self.frozen {
+			// regenerate the loop of the first tap at full level
+			let loop_length = self.taps.first().map_or(1.0, |tap| (tap.delay + self.offset).round().max(1.0));
+			self.buffer[self.write] = self.read(loop_length);
+		} else {
+			let input = match &mut self.bands {
+				Some(bands) => bands.process(input),
+				None => input,
+			};
+			self.buffer[self.write] = match &mut self.dc_blocker {
+				Some(filter) => filter.highpass(input),
+				None => input,
+			};
+		}
+		let mut modulation = self.lfo.next() * self.mod_depth;
+		if let Some(humanize) = &mut self.humanize {
+			// a repeat lasts as long as the first tap's delay
+			let offset = self.offset;
+			let period = self.taps.first().map_or(1.0, |tap| tap.delay + offset);
+			modulation += humanize.next(period);
+		}
+		if let Some(wow_flutter) = &mut self.wow_flutter {
+			modulation += wow_flutter.next();
+		}
+		modulation += self.cv_offset + self.cv_depth * to_f64(cv.clamp(-1.0, 1.0));
+		if let Buffer::Mapped(mapped) = &self.buffer {
+			let len = mapped.len;
+			mapped.positions.write.store(self.write, Ordering::Relaxed);
+			for (read, tap) in mapped.positions.reads.iter().zip(&self.taps) {
+				let behind = (tap.delay + self.offset).max(0.0) as usize % len;
+				read.store((self.write + len - behind) % len, Ordering::Relaxed);
+			}
+		}
+		let mut wet = 0.0;
+		let mut panned = [0.0; 2];
+		for index in 0..self.taps.len() {
+			let signal = self.read_tap(index, modulation) * self.taps[index].gain.next();
+			let [left, right] = self.taps[index].pan_gains;
+			wet += signal;
+			panned[0] += signal * left;
+			panned[1] += signal * right;
+		}
+		(wet, panned)
+	}
+
+	/// Adds the feedback of the frame last recorded, `other_wet` is the
+	/// delayed signal of the opposite channel for cross feedback.
+	/// With an external insert `returned` is what came back from it along with the
+	/// frames since it was sent, it is written that far back in place of the feedback.
+	/// Returns the feedback signal to send to the insert.
+	fn feed_back (&mut self, wet: Sample, other_wet: Sample, returned: Option<(Sample, usize)>) -> Sample {
+		// the filters and smoothers keep running while frozen
+		let feedback = flush_denormal(self.feedback_signal(wet, other_wet));
+		if !self.frozen {
+			match returned {
+				Some((returned, latency)) => {
+					// taps further back than the latency still get the repeat on time
+					let len = self.buffer.len();
+					let index = (self.write + len - latency.min(len - 1)) % len;
+					self.buffer[index] += flush_denormal(returned);
+				}
+				None => self.buffer[self.write] += feedback,
+			}
+			// the input and the repeats are stored together, like in an early digital delay
+			if let Some(crusher) = &mut self.crusher {
+				self.buffer[self.write] = crusher.process(self.buffer[self.write]);
+			}
+		}
+		feedback
+	}
+
+	/// Mixes the dry input and the delayed signal for the output port.
+	fn output (&mut self, input: Sample, mut wet: Sample, wet_gain: Sample) -> Sample {
+		if let Some(filter) = &mut self.wet_lowpass {
+			wet = filter.lowpass(wet);
+		}
+		if let Some(filter) = &mut self.wet_highpass {
+			wet = filter.highpass(wet);
+		}
+		let mix = self.mix.next();
+		input * (1.0 - mix) + wet * mix * wet_gain * self.polarity
+	}
+
+	/// Delays a single frame of a mono signal, the pan of the taps is ignored.
+	pub fn process (&mut self, input: f32) -> f32 {
+		let input = input as Sample;
+		let (wet, _) = self.record(input, 0.0);
+		self.feed_back(wet, 0.0, None);
+		to_f32(self.output(input, wet, 1.0))
+	}
+}
+
+/// Buffers of the ports patching external processing into the feedback path for one period.
+pub struct Insert<'a> {
+	pub sends: [&'a mut [f32]; 2],
+	/// `None` for a return that isn't connected, its channel then feeds back internally
+	pub returns: [Option<&'a [f32]>; 2],
+}
+
+/// Two delay lines processed side by side so they can feed into each other.
+/// Each line has its own buffer and write position, when one channel starts
+/// later or gets different settings the other keeps its timing.
+pub struct StereoDelay {
+	lines: [DelayLine; 2],
+	/// linear gain applied before the delay lines
+	input_gain: Smoothed,
+	/// linear gain applied to the output ports
+	output_gain: Smoothed,
+	/// gate in front of both delay lines, opened by the louder channel
+	gate: Option<Gate>,
+	limiter: Option<SoftLimiter>,
+	/// lowest gain of the limiter since it was last taken
+	limiter_gain: Sample,
+	/// gain of the input sent into the lines, closed in throw mode until thrown
+	send: Smoothed,
+	/// fading in of the delayed signal after transients of either channel
+	swell: Option<Swell>,
+	/// the lines delay the mid and side signal instead of left and right
+	mid_side: bool,
+	/// share of each delayed channel routed to the opposite output
+	swap: Sample,
+	bypassed: bool,
+	/// share of the unprocessed input on the outputs, ramps towards the bypass state
+	bypass_mix: Sample,
+	/// change of `bypass_mix` per frame
+	bypass_step: Sample,
+	/// frames processed since starting, until the first repeat comes out
+	fade_in_elapsed: usize,
+	/// gain of the outputs, ramps up from silence after starting
+	fade_in_gain: Sample,
+	/// gain of the repeats, ramps up from silence once the first of them comes out
+	wet_fade_in_gain: Sample,
+	/// change of both fade in gains per frame
+	fade_in_step: Sample,
+}
+
+impl StereoDelay {
+	/// Runs `lines` as the left and the right channel at unity gain, fading in
+	/// over 64 frames after starting and once the first repeat comes out.
+	pub fn new (lines: [DelayLine; 2]) -> Self {
+		StereoDelay {
+			lines,
+			input_gain: Smoothed::new(1.0),
+			output_gain: Smoothed::new(1.0),
+			gate: None,
+			limiter: None,
+			limiter_gain: 1.0,
+			send: Smoothed::new(1.0),
+			swell: None,
+			mid_side: false,
+			swap: 0.0,
+			bypassed: false,
+			bypass_mix: 0.0,
+			bypass_step: 1.0,
+			fade_in_elapsed: 0,
+			fade_in_gain: 0.0,
+			wet_fade_in_gain: 0.0,
+			fade_in_step: 1.0 / 64.0,
+		}
+	}
+
+	/// The left and the right delay line.
+	pub fn lines_mut (&mut self) -> &mut [DelayLine; 2] {
+		&mut self.lines
+	}
+
+	/// Sets the linear gain in front of the lines.
+	pub fn set_input_gain (&mut self, gain: f32) {
+		self.input_gain.set(gain);
+	}
+
+	/// Sets the linear gain of the outputs.
+	pub fn set_output_gain (&mut self, gain: f32) {
+		self.output_gain.set(gain);
+	}
+
+	/// Sets the linear gain of the input sent into the lines, the dry signal is not affected.
+	pub fn set_send (&mut self, send: f32) {
+		self.send.set(send);
+	}
+
+	/// Keeps quiet input out of both lines, `None` sends everything.
+	pub fn set_gate (&mut self, gate: Option<Gate>) {
+		self.gate = gate;
+	}
+
+	/// Limits both outputs together, `None` leaves them unlimited.
+	pub fn set_limiter (&mut self, limiter: Option<SoftLimiter>) {
+		self.limiter = limiter;
+	}
+
+	/// The lowest gain of the limiter since this was last called, `None` without a limiter.
+	pub fn take_limiter_gain (&mut self) -> Option<f32> {
+		self.limiter.as_ref()?;
+		Some(to_f32(std::mem::replace(&mut self.limiter_gain, 1.0)))
+	}
+
+	/// Fades the delayed signal in after transients, `None` keeps it steady.
+	pub fn set_swell (&mut self, swell: Option<Swell>) {
+		self.swell = swell;
+	}
+
+	/// Delays the mid and the side signal instead of left and right.
+	pub fn set_mid_side (&mut self, mid_side: bool) {
+		self.mid_side = mid_side;
+	}
+
+	/// Routes a share of each delayed channel to the opposite output, 1.0 swaps them.
+	pub fn set_swap (&mut self, swap: f32) {
+		self.swap = swap as Sample;
+	}
+
+	/// Fades the outputs in over `fade_frames` after starting, and the repeats
+	/// over as many once the shortest tap has filled with input.
+	pub fn set_fade_in (&mut self, fade_frames: usize) {
+		let gain = if fade_frames == 0 { 1.0 } else { 0.0 };
+		self.fade_in_gain = gain;
+		self.wet_fade_in_gain = gain;
+		self.fade_in_step = 1.0 / fade_frames.max(1) as Sample;
+	}
+
+	/// Carries the settings of both lines, the gate, the limiter, the swell and the fade in
+	/// over from `previous` to `sample_rate`, see `DelayLine::set_sample_rate`. The smoothing
+	/// and the bypass fade are set anew with `set_smoothing` and `set_bypassed`.
+	pub fn set_sample_rate (&mut self, previous: usize, sample_rate: usize) -> bool {
+		if let Some(gate) = &mut self.gate {
+			gate.set_sample_rate(sample_rate);
+		}
+		if let Some(limiter) = &mut self.limiter {
+			limiter.set_sample_rate(sample_rate);
+		}
+		if let Some(swell) = &mut self.swell {
+			swell.set_sample_rate(sample_rate);
+		}
+		self.fade_in_step *= previous as Sample / sample_rate as Sample;
+		let [line_1, line_2] = &mut self.lines;
+		line_1.set_sample_rate(previous, sample_rate) & line_2.set_sample_rate(previous, sample_rate)
+	}
+
+	/// Sets the coefficient with which all gains, mix and feedback follow changes.
+	pub fn set_smoothing (&mut self, coefficient: f32) {
+		self.input_gain.set_coefficient(coefficient);
+		self.output_gain.set_coefficient(coefficient);
+		self.send.set_coefficient(coefficient);
+		for line in &mut self.lines {
+			line.set_smoothing(coefficient);
+		}
+	}
+
+	/// Bypasses the delay, crossfading to the unprocessed input over `fade_frames`.
+	pub fn set_bypassed (&mut self, bypassed: bool, fade_frames: usize) {
+		self.bypassed = bypassed;
+		self.bypass_step = 1.0 / fade_frames.max(1) as Sample;
+	}
+
+	/// Delays both input buffers into the output buffers, the delayed
+	/// signal is scaled per frame by `wet_gains` if given and the read
+	/// positions follow the control voltage `cv`, see `DelayLine::set_cv`.
+	/// Buffers of different lengths are processed as far as the shortest,
+	/// the outputs and sends are silent past that.
+	pub fn process (&mut self, inputs: [&[f32]; 2], outputs: [&mut [f32]; 2], wet_gains: Option<&[f32]>, cv: Option<&[f32]>, mut insert: Option<Insert>) {
+		let [in_1, in_2] = inputs;
+		let [out_1, out_2] = outputs;
+		let mut frames = in_1.len().min(in_2.len()).min(out_1.len()).min(out_2.len());
+		if let Some(insert) = &insert {
+			frames = frames.min(insert.sends[0].len()).min(insert.sends[1].len());
+		}
+		out_1[frames..].fill(0.0);
+		out_2[frames..].fill(0.0);
+		if let Some(insert) = &mut insert {
+			for send in &mut insert.sends {
+				send[frames..].fill(0.0);
+			}
+		}
+		let [line_1, line_2] = &mut self.lines;
+		// without a fade going on the dry signal is blended in for the whole period at once
+		let ramping = self.bypass_mix != if self.bypassed { 1.0 } else { 0.0 } || self.fade_in_gain < 1.0;
+
+		for i in 0..frames {
+			let (dry_1, dry_2) = (in_1[i] as Sample, in_2[i] as Sample);
+			let input_gain = self.input_gain.next();
+			let (mut input_1, mut input_2) = (dry_1 * input_gain, dry_2 * input_gain);
+			if self.mid_side {
+				(input_1, input_2) = ((input_1 + input_2) * 0.5, (input_1 - input_2) * 0.5);
+			}
+			// the gate only keeps the input out of the lines, the dry signal passes untouched
+			let gate_gain = self.gate.as_mut().map_or(1.0, |gate| gate.next(input_1.abs().max(input_2.abs())));
+			let send = gate_gain * self.send.next();
+			let cv = cv.and_then(|cv| cv.get(i)).map_or(0.0, |&cv| cv as Sample);
+			let (wet_1, panned_1) = line_1.record(input_1 * send, cv);
+			let (wet_2, panned_2) = line_2.record(input_2 * send, cv);
+			match &mut insert {
+				Some(insert) => {
+					// what comes back was sent one period earlier at the least
+					// a short return counts as disconnected for the frames it lacks
+					let [return_1, return_2] = insert.returns.map(|returned| returned.and_then(|returned| returned.get(i)).map(|&returned| (returned as Sample, frames)));
+					insert.sends[0][i] = to_f32(line_1.feed_back(wet_1, wet_2, return_1));
+					insert.sends[1][i] = to_f32(line_2.feed_back(wet_2, wet_1, return_2));
+				}
+				None => {
+					line_1.feed_back(wet_1, wet_2, None);
+					line_2.feed_back(wet_2, wet_1, None);
+				}
+			}
+			// each line repeats its own input, the pan of its taps decides where that ends up
+			let out_wet_1 = panned_1[0] + panned_2[0];
+			let out_wet_2 = panned_1[1] + panned_2[1];
+			let (out_wet_1, out_wet_2) = (
+				out_wet_1 + (out_wet_2 - out_wet_1) * self.swap,
+				out_wet_2 + (out_wet_1 - out_wet_2) * self.swap,
+			);
+
+			// whatever the buffers held before the input reached the taps would thump
+			if self.wet_fade_in_gain < 1.0 {
+				let onset = match (line_1.onset(), line_2.onset()) {
+					(Some(onset_1), Some(onset_2)) => onset_1.min(onset_2),
+					(onset_1, onset_2) => onset_1.or(onset_2).unwrap_or(0.0),
+				};
+				if self.fade_in_elapsed as f64 >= onset.round() {
+					self.wet_fade_in_gain = (self.wet_fade_in_gain + self.fade_in_step).min(1.0);
+				} else {
+					self.fade_in_elapsed += 1;
+				}
+			}
+			let swell_gain = self.swell.as_mut().map_or(1.0, |swell| swell.next(dry_1.abs().max(dry_2.abs())));
+			let wet_gain = wet_gains.and_then(|gains| gains.get(i)).map_or(1.0, |&gain| gain as Sample) * swell_gain * self.wet_fade_in_gain;
+			let output_gain = self.output_gain.next();
+			let mut processed_1 = line_1.output(input_1, out_wet_1, wet_gain) * output_gain;
+			let mut processed_2 = line_2.output(input_2, out_wet_2, wet_gain) * output_gain;
+			if self.mid_side {
+				(processed_1, processed_2) = (processed_1 + processed_2, processed_1 - processed_2);
+			}
+			if let Some(limiter) = &mut self.limiter {
+				let gain = limiter.next(processed_1.abs().max(processed_2.abs()));
+				self.limiter_gain = self.limiter_gain.min(gain);
+				processed_1 *= gain;
+				processed_2 *= gain;
+			}
+
+			if !ramping {
+				out_1[i] = to_f32(processed_1);
+				out_2[i] = to_f32(processed_2);
+				continue;
+			}
+			// the lines keep running while bypassed, so the repeats are there when coming back.
+			// dry and processed signal are correlated, a linear fade keeps the level constant.
+			let target = if self.bypassed { 1.0 } else { 0.0 };
+			if self.bypass_mix != target {
+				let step = (target - self.bypass_mix).clamp(-self.bypass_step, self.bypass_step);
+				self.bypass_mix += step;
+			}
+			// jumping straight to full level on starting thumps
+			if self.fade_in_gain < 1.0 {
+				self.fade_in_gain = (self.fade_in_gain + self.fade_in_step).min(1.0);
+			}
+			out_1[i] = to_f32((processed_1 + (dry_1 - processed_1) * self.bypass_mix) * self.fade_in_gain);
+			out_2[i] = to_f32((processed_2 + (dry_2 - processed_2) * self.bypass_mix) * self.fade_in_gain);
+		}
+		if !ramping && self.bypass_mix != 0.0 {
+			let mix = to_f32(self.bypass_mix);
+			blend(&mut out_1[..frames], &in_1[..frames], mix);
+			blend(&mut out_2[..frames], &in_2[..frames], mix);
+		}
+	}
+}
+
+/// A path through a `DelayMatrix`.
+struct MatrixPath {
+	input: usize,
+	output: usize,
+	delay: f64,
+	gain: Sample,
+}
+
+/// Routes several inputs to several outputs, each path with its own delay and gain.
+/// Unlike a `DelayLine` there is no feedback or modulation, just one plain
+/// interpolated read per path out of a ring buffer per input.
+pub struct DelayMatrix {
+	buffers: Vec<Vec<Sample>>,
+	/// frames written so far, the position in each ring follows from it
+	written: usize,
+	paths: Vec<MatrixPath>,
+	/// most frames a period may have to fit into the buffers
+	period: usize,
+}
+
+impl DelayMatrix {
+	/// Creates a matrix of `inputs` inputs without any paths.
+	pub fn new (inputs: usize) -> Self {
+		DelayMatrix {
+			buffers: vec![vec![]; inputs],
+			written: 0,
+			paths: vec![],
+			period: 0,
+		}
+	}
+
+	/// Adds a path from `input` to `output` delayed by `delay` frames, negative delays count as none.
+	pub fn add_path (&mut self, input: usize, output: usize, delay: f64, gain: f32) {
+		self.paths.push(MatrixPath { input, output, delay: delay.max(0.0), gain: gain as Sample });
+		self.resize();
+	}
+
+	/// Makes room for periods of up to `frames`, allocates and clears the buffers if they grow.
+	pub fn reserve (&mut self, frames: usize) {
+		if frames > self.period {
+			self.period = frames;
+			self.resize();
+		}
+	}
+
+	fn resize (&mut self) {
+		let lens: Vec<usize> = (0..self.buffers.len())
+			.map(|input| {
+				let longest = self.paths.iter()
+					.filter(|path| path.input == input)
+					.map(|path| path.delay)
+					.fold(0.0, f64::max);
+				// a period is written before it is read, the interpolation reaches one frame further
+				longest.ceil() as usize + self.period + 2
+			})
+			.collect();
+		if self.buffers.iter().zip(&lens).any(|(buffer, &len)| buffer.len() < len) {
+			// starting over, as the rings of the inputs that keep their size would be out of step
+			for (buffer, len) in self.buffers.iter_mut().zip(lens) {
+				*buffer = vec![0.0; len.max(buffer.len())];
+			}
+			self.written = 0;
+		}
+	}
+
+	/// Delays one period of `inputs` into `outputs`. Periods longer than reserved
+	/// are processed as far as they fit, as are inputs shorter than the others,
+	/// the outputs are silent past that.
+	pub fn process<'a, I> (&mut self, inputs: I, outputs: impl IntoIterator<Item = &'a mut [f32]>)
+	where
+		I: IntoIterator<Item = &'a [f32]>,
+		I::IntoIter: Clone,
+	{
+		let frames = self.write(inputs);
+		for (index, output) in outputs.into_iter().enumerate() {
+			self.read(index, output, frames);
+		}
+		self.advance(frames);
+	}
+
+	/// Writes one period of `inputs` into the rings and returns the frames that fit, the first
+	/// step of `process`. The outputs can then be read on separate threads before `advance`.
+	pub fn write<'a, I> (&mut self, inputs: I) -> usize
+	where
+		I: IntoIterator<Item = &'a [f32]>,
+		I::IntoIter: Clone,
+	{
+		let inputs = inputs.into_iter();
+		// every ring moves on by the same frames, or the paths would drift apart
+		let frames = inputs.clone()
+			.take(self.buffers.len())
+			.fold(self.period, |frames, input| frames.min(input.len()));
+		for (buffer, input) in self.buffers.iter_mut().zip(inputs) {
+			// in one piece up to the end of the ring and the rest from its start
+			let start = self.written % buffer.len();
+			let split = frames.min(buffer.len() - start);
+			copy_in(&mut buffer[start..start + split], &input[..split]);
+			copy_in(&mut buffer[..frames - split], &input[split..frames]);
+		}
+		frames
+	}
+
+	/// Sums the paths into output `index` for the `frames` last written.
+	pub fn read (&self, index: usize, output: &mut [f32], frames: usize) {
+		let frames = frames.min(output.len());
+		output.fill(0.0);
+		for path in self.paths.iter().filter(|path| path.output == index) {
+			let buffer = &self.buffers[path.input];
+			let len = buffer.len();
+			let whole = path.delay.floor() as usize;
+			let frac = (path.delay - whole as f64) as Sample;
+			let mut offset = 0;
+			while offset < frames {
+				let newer = (self.written + offset + len - whole) % len;
+				if newer == 0 {
+					// the older frame wraps around to the end of the ring
+					mix_interpolated(&mut output[offset..offset + 1], &buffer[..1], &buffer[len - 1..], frac, path.gain);
+					offset += 1;
+					continue;
+				}
+				let run = (frames - offset).min(len - newer);
+				mix_interpolated(&mut output[offset..offset + run], &buffer[newer..newer + run], &buffer[newer - 1..newer - 1 + run], frac, path.gain);
+				offset += run;
+			}
+		}
+	}
+
+	/// Moves the rings on by the `frames` written and read, the last step of `process`.
+	pub fn advance (&mut self, frames: usize) {
+		self.written += frames;
+	}
+}
+
+/// Delays MIDI events by whole frames, each keeps its place within the period.
+/// The queue has a fixed room, events that don't fit are dropped.
+pub struct MidiDelay {
+	delay: usize,
+	/// frames processed so far
+	now: u64,
+	/// frame each queued event is due at and the length of its bytes
+	events: VecDeque<(u64, usize)>,
+	bytes: VecDeque<u8>,
+	/// holds the bytes of an event while it is handed out
+	scratch: Vec<u8>,
+	room: (usize, usize),
+}
+
+impl MidiDelay {
+	/// Creates a delay of `delay` frames with room for `events` events of `bytes` bytes overall.
+	pub fn new (delay: usize, events: usize, bytes: usize) -> Self {
+		MidiDelay {
+			delay,
+			now: 0,
+			events: VecDeque::with_capacity(events),
+			bytes: VecDeque::with_capacity(bytes),
+			scratch: Vec::with_capacity(bytes),
+			room: (events, bytes),
+		}
+	}
+
+	/// Changes the delay for events pushed from now on. Those are never sent before
+	/// the ones already queued, so a shorter delay holds them back until the queue caught up.
+	pub fn set_delay (&mut self, delay: usize) {
+		self.delay = delay;
+	}
+
+	pub fn delay (&self) -> usize {
+		self.delay
+	}
+
+	/// Queues an event `time` frames into the current period, returns false if there's no room for it.
+	pub fn push (&mut self, time: usize, bytes: &[u8]) -> bool {
+		if self.events.len() == self.room.0 || self.room.1 - self.bytes.len() < bytes.len() {
+			return false;
+		}
+		let due = self.now + (time + self.delay) as u64;
+		let due = self.events.back().map_or(due, |&(last, _)| due.max(last));
+		self.events.push_back((due, bytes.len()));
+		self.bytes.extend(bytes);
+		true
+	}
+
+	/// Hands the events due within the current period of `frames` frames to `send`
+	/// along with their time into the period, then moves on to the next period.
+	pub fn release (&mut self, frames: usize, mut send: impl FnMut(usize, &[u8])) {
+		let end = self.now + frames as u64;
+		while let Some(&(due, len)) = self.events.front() {
+			if due >= end {
+				break;
+			}
+			self.events.pop_front();
+			self.scratch.clear();
+			self.scratch.extend(self.bytes.drain(..len));
+			send((due - self.now) as usize, &self.scratch);
+		}
+		self.now = end;
+	}
+
+	/// Drops all queued events.
+	pub fn clear (&mut self) {
+		self.events.clear();
+		self.bytes.clear();
+	}
+}
+
+/// Carries a stereo stream from one clock to another running at about the same rate, like
+/// between two sound cards, through a queue in between. The resampling ratio follows the
+/// drift of the clocks so that around `target` frames stay queued.
+pub struct DriftResampler {
+	/// input frames per output frame at the nominal sample rates
+	nominal: f64,
+	/// part of the ratio correcting for the drift seen so far
+	drift: f64,
+	/// how far the output is between `previous` and `current`, from 0 to 1
+	position: f64,
+	previous: [Sample; 2],
+	current: [Sample; 2],
+	target: f64,
+}
+
+impl DriftResampler {
+	/// Proportional and integral gain per frame off the target. With these a step settles
+	/// critically damped within about half a minute at common sample rates.
+	const PROPORTIONAL: f64 = 2e-6;
+	const INTEGRAL: f64 = 1e-12;
+	/// largest correction of the ratio, far beyond the drift of any two crystals
+	const MAX_DRIFT: f64 = 5e-3;
+
+	/// Resamples from `input_rate` to `output_rate`, keeping `target` frames queued.
+	pub fn new (input_rate: usize, output_rate: usize, target: usize) -> Self {
+		DriftResampler {
+			nominal: input_rate as f64 / output_rate as f64,
+			drift: 0.0,
+			position: 0.0,
+			previous: [0.0; 2],
+			current: [0.0; 2],
+			target: target as f64,
+		}
+	}
+
+	/// Current ratio of input to output frames.
+	pub fn ratio (&self) -> f64 {
+		self.nominal * (1.0 + self.drift)
+	}
+
+	/// Fills the outputs with frames pulled from `next`, which gives `None` once the queue is empty,
+	/// `queued` is how many frames the queue holds. The outputs stay silent while there are none.
+	/// Far more frames than the target, e.g. from starting the sending side first, are skipped.
+	pub fn process (&mut self, queued: usize, outputs: [&mut [f32]; 2], mut next: impl FnMut() -> Option<[f32; 2]>) {
+		let [out_1, out_2] = outputs;
+		let mut queued = queued as f64;
+		if queued > self.target * 4.0 {
+			while queued > self.target && next().is_some() {
+				queued -= 1.0;
+			}
+		}
+		let error = queued - self.target;
+		let frames = out_1.len().min(out_2.len());
+		self.drift = (self.drift + error * Self::INTEGRAL * frames as f64).clamp(-Self::MAX_DRIFT, Self::MAX_DRIFT);
+		let ratio = self.nominal * (1.0 + (self.drift + error * Self::PROPORTIONAL).clamp(-Self::MAX_DRIFT, Self::MAX_DRIFT));
+		for i in 0..frames {
+			self.position += ratio;
+			while self.position >= 1.0 {
+				self.position -= 1.0;
+				self.previous = self.current;
+				self.current = next().map_or([0.0; 2], |frame| frame.map(|sample| sample as Sample));
+			}
+			let position = self.position as Sample;
+			out_1[i] = to_f32(self.previous[0] + (self.current[0] - self.previous[0]) * position);
+			out_2[i] = to_f32(self.previous[1] + (self.current[1] - self.previous[1]) * position);
+		}
+		out_1[frames..].fill(0.0);
+		out_2[frames..].fill(0.0);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TIMEBASE: Timebase = Timebase {
+		sample_rate: 48000,
+		tempo: Tempo { bpm: 120.0, beat_type: 4.0 },
+		speed_of_sound: 343.0,
+	};
+
+	/// Runs `input` through `line` frame by frame.
+	fn run (line: &mut DelayLine, input: &[f32]) -> Vec<f32> {
+		input.iter().map(|&frame| line.process(frame)).collect()
+	}
+
+	fn impulse (frames: usize) -> Vec<f32> {
+		let mut signal = vec![0.0; frames];
+		signal[0] = 1.0;
+		signal
+	}
+
+	#[test]
+	fn parses_delay_times () {
+		assert_eq!("4800".parse(), Ok(DelayTime::Frames(4800.0)));
+		assert_eq!("120ms".parse(), Ok(DelayTime::Millis(120.0)));
+		assert_eq!("0.5s".parse(), Ok(DelayTime::Seconds(0.5)));
+		assert_eq!("1/4".parse(), Ok(DelayTime::Note(0.25)));
+		assert_eq!("1/8d".parse(), Ok(DelayTime::Note(0.1875)));
+		assert_eq!("12.5m".parse(), Ok(DelayTime::Distance(12.5)));
+		assert_eq!("-256".parse(), Ok(DelayTime::Frames(-256.0)));
+		assert!("12 parsecs".parse::<DelayTime>().is_err());
+		assert!("1/0".parse::<DelayTime>().is_err());
+	}
+
+	#[test]
+	fn converts_delay_times_to_frames () {
+		assert_eq!(DelayTime::Millis(10.0).to_frames(&TIMEBASE), 480.0);
+		assert_eq!(DelayTime::Seconds(0.5).to_frames(&TIMEBASE), 24000.0);
+		// a quarter note at 120 bpm
+		assert_eq!(DelayTime::Note(0.25).to_frames(&TIMEBASE), 24000.0);
+		assert_eq!(DelayTime::Distance(343.0).to_frames(&TIMEBASE), 48000.0);
+		assert_eq!(DelayTime::nearest_note(0.24, TIMEBASE.tempo), DelayTime::Note(0.125));
+	}
+
+	#[test]
+	fn delays_by_whole_frames () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(3.0, 1.0, 0.0);
+		assert_eq!(run(&mut line, &impulse(6)), [0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+	}
+
+	#[test]
+	fn interpolates_fractional_delays () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(1.5, 1.0, 0.0);
+		assert_eq!(run(&mut line, &impulse(4)), [0.0, 0.5, 0.5, 0.0]);
+	}
+
+	#[test]
+	fn sinc_interpolation_wraps_around_the_ring () {
+		let mut line = DelayLine::new(0);
+		line.set_interpolation(Interpolation::Sinc(16));
+		line.add_tap(20.0, 1.0, 0.0);
+		// the ring is barely longer than the kernel reaches, so reads wrap around often
+		let input: Vec<f32> = (0..200).map(|frame| if frame % 37 == 0 { 1.0 } else { 0.0 }).collect();
+		let output = run(&mut line, &input);
+		for (frame, &sample) in output.iter().enumerate().skip(20) {
+			assert!((sample - input[frame - 20]).abs() < 1e-6, "frame {}: {}", frame, sample);
+		}
+
+		line.set_tap_delay(0, 20.5);
+		let output = run(&mut line, &[1.0; 100]);
+		assert!((output[99] - 1.0).abs() < 1e-3);
+	}
+
+	#[test]
+	fn settings_follow_a_change_of_the_sample_rate () {
+		let line_at = |sample_rate| {
+			let mut line = DelayLine::new(0);
+			line.add_tap(4.0, 1.0, 0.0);
+			line.set_feedback(0.5);
+			line.set_feedback_filters(Some(2000.0), Some(100.0), sample_rate);
+			line.set_wet_filters(Some(5000.0), None, sample_rate);
+			line.set_feedback_ceiling(-6.0, sample_rate);
+			line
+		};
+		let (mut changed, mut fresh) = (line_at(48000), line_at(96000));
+		assert!(changed.set_sample_rate(48000, 96000));
+		assert_close(&run(&mut changed, &impulse(64)), &run(&mut fresh, &impulse(64)));
+	}
+
+	#[test]
+	fn deeper_modulation_at_a_higher_sample_rate_cuts_the_taps () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(100.0, 1.0, 0.0);
+		line.set_modulation(Lfo::new(1.0, LfoShape::Sine, 0.0, 48000), 10.0);
+		let capacity = line.capacity();
+		line.set_tap_delay(0, capacity);
+		assert!(!line.set_sample_rate(48000, 96000));
+		assert!(line.capacity() < capacity);
+		assert_eq!(line.taps[0].delay, line.capacity());
+	}
+
+	#[test]
+	fn vectorized_dot_product_matches_the_scalar_one () {
+		let a: Vec<Sample> = (0..37).map(|i| i as Sample * 0.25).collect();
+		let b: Vec<Sample> = (0..37).map(|i| 1.0 - i as Sample * 0.125).collect();
+		for len in [0, 1, 8, 9, 16, 37] {
+			assert!((dot(&a[..len], &b[..len]) - dot_scalar(&a[..len], &b[..len])).abs() < 1e-3);
+		}
+	}
+
+	#[test]
+	fn vectorized_mix_kernels_match_the_scalar_ones () {
+		let newer: Vec<Sample> = (0..37).map(|i| i as Sample * 0.25).collect();
+		let older: Vec<Sample> = (0..37).map(|i| 1.0 - i as Sample * 0.125).collect();
+		let dry: Vec<f32> = (0..37).map(|i| (i as f32 * 0.7).sin()).collect();
+		for len in [0, 1, 8, 9, 16, 37] {
+			let (mut vectorized, mut scalar) = (vec![0.5; len], vec![0.5; len]);
+			mix_interpolated(&mut vectorized, &newer[..len], &older[..len], 0.3, 0.8);
+			mix_interpolated_scalar(&mut scalar, &newer[..len], &older[..len], 0.3, 0.8);
+			assert_close(&vectorized, &scalar);
+			blend(&mut vectorized, &dry[..len], 0.4);
+			blend_scalar(&mut scalar, &dry[..len], 0.4);
+			assert_close(&vectorized, &scalar);
+		}
+	}
+
+	#[test]
+	fn output_is_silent_until_the_delay_has_passed () {
+		for interpolation in [Interpolation::Linear, Interpolation::Allpass, Interpolation::Sinc(32)] {
+			let mut line = DelayLine::new(0);
+			line.set_interpolation(interpolation);
+			line.set_wet_filters(Some(5000.0), Some(20.0), 48000);
+			line.set_feedback(0.7);
+			line.add_tap(480.5, 1.0, 0.0);
+			let output = run(&mut line, &[1.0; 1000]);
+			// the lines start out filled with zeros, so there's nothing but silence to read
+			let first_sound = output.iter().position(|&frame| frame != 0.0);
+			assert!(first_sound >= Some(480 - 16), "{:?} at {:?}", interpolation, first_sound);
+		}
+	}
+
+	#[test]
+	fn sums_taps_with_their_gains () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(1.0, 0.5, 0.0);
+		line.add_tap(2.0, 0.25, 0.0);
+		assert_eq!(run(&mut line, &impulse(4)), [0.0, 0.5, 0.25, 0.0]);
+	}
+
+	#[test]
+	fn mixes_dry_and_delayed_signal () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(1.0, 1.0, 0.0);
+		line.set_mix(0.25);
+		assert_eq!(run(&mut line, &impulse(3)), [0.75, 0.25, 0.0]);
+	}
+
+	#[test]
+	fn feedback_repeats_decay () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(2.0, 1.0, 0.0);
+		line.set_feedback(0.5);
+		let output = run(&mut line, &impulse(9));
+		assert_eq!(output, [0.0, 0.0, 1.0, 0.0, 0.5, 0.0, 0.25, 0.0, 0.125]);
+	}
+
+	#[test]
+	fn feedback_tails_end_in_silence_not_denormals () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(1.0, 1.0, 0.0);
+		line.set_feedback(0.5);
+		line.set_feedback_filters(Some(10000.0), None, 48000);
+		// halving every frame, a 64 bit float leaves the normal range after about 1000 repeats
+		let output = run(&mut line, &impulse(2000));
+		assert!(output.iter().all(|frame| *frame == 0.0 || frame.is_normal()));
+		assert_eq!(output.last(), Some(&0.0));
+	}
+
+	#[test]
+	fn cleared_lines_drop_their_tails () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(2.0, 1.0, 0.0);
+		line.set_feedback(0.5);
+		line.set_shimmer(Some(12.0), 48000);
+		run(&mut line, &impulse(3));
+		line.clear();
+		assert!(run(&mut line, &[0.0; 100]).iter().all(|&frame| frame == 0.0));
+	}
+
+	#[test]
+	fn frozen_line_keeps_looping () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(2.0, 1.0, 0.0);
+		run(&mut line, &[1.0, 0.0]);
+		line.set_frozen(true);
+		assert_eq!(run(&mut line, &[0.0, 0.5, 0.0, 0.5, 0.0, 0.5]), [1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+	}
+
+	#[test]
+	fn reverse_plays_each_window_backwards () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(4.0, 1.0, 0.0);
+		line.set_reverse(true);
+		let output = run(&mut line, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 0.0, 0.0, 0.0, 0.0]);
+		assert_eq!(output, [0.0, 0.0, 0.0, 0.0, 4.0, 3.0, 2.0, 1.0, 8.0, 7.0, 6.0, 5.0]);
+	}
+
+	#[test]
+	fn shuffled_grains_play_forwards_from_within_the_delay () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(4.0, 1.0, 0.0);
+		line.set_shuffle(Some(2));
+		let ramp: Vec<f32> = (1..=400).map(|frame| frame as f32).collect();
+		let output = run(&mut line, &ramp);
+		// with two slices each grain starts either at the delay or a grain further back
+		let behind: Vec<f32> = output.iter().zip(&ramp).skip(8).map(|(out, input)| input - out).collect();
+		assert!(behind.iter().all(|&behind| behind == 4.0 || behind == 6.0), "{:?}", behind);
+		assert!(behind.contains(&4.0) && behind.contains(&6.0));
+		assert!(behind.chunks(2).all(|grain| grain[0] == grain[1]));
+	}
+
+	#[test]
+	fn allpass_interpolation_keeps_the_level_of_fractional_delays () {
+		let mut line = DelayLine::new(0);
+		line.set_interpolation(Interpolation::Allpass);
+		line.add_tap(1.5, 1.0, 0.0);
+		// linear interpolation halves an impulse into two, losing half of its energy
+		let energy: f32 = run(&mut line, &impulse(200)).iter().map(|frame| frame * frame).sum();
+		assert!((energy - 1.0).abs() < 1e-3, "{}", energy);
+		let step = run(&mut line, &[1.0; 200]);
+		assert!((step[199] - 1.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn feedback_filters_keep_their_band_out_of_the_repeats () {
+		// with a feedback of 0.5 the repeats double what goes through the filters
+		let mut line = DelayLine::new(0);
+		line.add_tap(10.0, 1.0, 0.0);
+		line.set_mix(0.5);
+		line.set_feedback(0.5);
+		line.set_feedback_filters(None, Some(100.0), 48000);
+		let dc = run(&mut line, &[1.0; 48000]);
+		assert!((dc[47999] - 1.0).abs() < 0.01, "{}", dc[47999]);
+
+		let mut line = DelayLine::new(0);
+		line.add_tap(10.0, 1.0, 0.0);
+		line.set_mix(0.5);
+		line.set_feedback(0.5);
+		line.set_feedback_filters(Some(1000.0), None, 48000);
+		let nyquist: Vec<f32> = (0..4800).map(|frame| if frame % 2 == 0 { 1.0 } else { -1.0 }).collect();
+		let output = run(&mut line, &nyquist);
+		assert!(output[4799].abs() < 1.1, "{}", output[4799]);
+	}
+
+	#[test]
+	fn shimmer_raises_each_repeat_by_an_octave () {
+		let mut shifter = PitchShifter::new(12.0, 48000);
+		let sine = |frame: usize| (std::f64::consts::TAU * 100.0 * frame as f64 / 48000.0).sin() as Sample;
+		let output: Vec<Sample> = (0..48000).map(|frame| shifter.process(sine(frame))).collect();
+		// an octave above 100 Hz crosses zero 200 times in half a second, a few more where the windows overlap
+		let crossings = output[4800..28800].windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count();
+		assert!((190..=220).contains(&crossings), "{}", crossings);
+	}
+
+	#[test]
+	fn humanized_repeats_land_late_but_never_early () {
+		let mut late = false;
+		for seed in (1..20).map(|seed: u32| seed.wrapping_mul(0x9e37_79b9)) {
+			let mut line = DelayLine::new(0);
+			line.add_tap(100.0, 1.0, 0.0);
+			line.set_humanize(Some(20.0), seed);
+			let output = run(&mut line, &impulse(200));
+			let loudest = (0..200).max_by(|&a, &b| output[a].abs().total_cmp(&output[b].abs())).unwrap();
+			assert!((100..=121).contains(&loudest), "seed {} at {}", seed, loudest);
+			assert!(output[..100].iter().all(|&frame| frame == 0.0));
+			late |= loudest > 101;
+		}
+		assert!(late);
+	}
+
+	#[test]
+	fn wow_and_flutter_only_make_the_delay_longer () {
+		let mut wow_flutter = WowFlutter::new(40.0, 10.0, 0x2545_f491, 48000);
+		let drift: Vec<f64> = (0..240_000).map(|_| wow_flutter.next()).collect();
+		assert!(drift.iter().all(|&drift| (0.0..=50.0).contains(&drift)));
+		let (lowest, highest) = drift.iter().fold((f64::MAX, f64::MIN), |(lowest, highest), &drift| (lowest.min(drift), highest.max(drift)));
+		assert!(highest - lowest > 10.0);
+	}
+
+	#[test]
+	fn feedback_ceiling_stops_runaway_repeats () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(10.0, 1.0, 0.0);
+		line.set_feedback(1.0);
+		line.set_feedback_ceiling(-6.0, 48000);
+		// the input adds up with every repeat, only the ceiling keeps the repeats bounded
+		let ceiling = 1.0 + db_to_gain(-6.0);
+		assert!(run(&mut line, &[1.0; 4800]).iter().all(|&frame| frame <= ceiling + 1e-4));
+	}
+
+	#[test]
+	fn crusher_quantizes_and_holds_the_line () {
+		let mut crusher = Crusher::new(Some(2), None, 48000);
+		let output: Vec<Sample> = [0.3, 0.2, -0.8, 1.0].iter().map(|&frame| crusher.process(frame)).collect();
+		assert_eq!(output, [0.5, 0.0, -1.0, 1.0]);
+
+		let mut crusher = Crusher::new(None, Some(12000.0), 48000);
+		let output: Vec<Sample> = (1..=8).map(|frame| crusher.process(frame as Sample)).collect();
+		assert_eq!(output, [1.0, 1.0, 1.0, 1.0, 5.0, 5.0, 5.0, 5.0]);
+	}
+
+	#[test]
+	fn bands_sum_back_to_a_flat_response () {
+		let mut bands = BandSplitter::new(&[300.0, 3000.0], &[0, 0, 0], 48000);
+		let response: Vec<Sample> = impulse(48000).iter().map(|&frame| bands.process(frame as Sample)).collect();
+		// an all-pass keeps the energy of every frequency
+		let energy: Sample = response.iter().map(|frame| frame * frame).sum();
+		assert!((energy - 1.0).abs() < 0.01, "{}", energy);
+		// the bands are delayed on top of that
+		let mut bands = BandSplitter::new(&[300.0], &[0, 24], 48000);
+		let mut delayed = BandSplitter::new(&[300.0], &[24, 24], 48000);
+		let single: Vec<Sample> = impulse(500).iter().map(|&frame| bands.process(frame as Sample)).collect();
+		let both: Vec<Sample> = impulse(500).iter().map(|&frame| delayed.process(frame as Sample)).collect();
+		assert!(both[..24].iter().all(|&frame| frame == 0.0));
+		assert!(single[..24].iter().any(|&frame| frame != 0.0));
+	}
+
+	#[test]
+	fn negative_delays_are_taken_from_the_offset () {
+		let mut line = DelayLine::new(0);
+		line.set_offset(2.0);
+		line.add_tap(-1.0, 1.0, 0.0);
+		assert_eq!(run(&mut line, &impulse(3)), [0.0, 1.0, 0.0]);
+		// it can't reach into the future
+		line.set_tap_delay(0, -5.0);
+		assert_eq!(line.taps[0].delay, -2.0);
+	}
+
+	#[test]
+	fn delay_changes_stay_within_the_buffer () {
+		let mut line = DelayLine::new(0);
+		line.add_tap(100.0, 1.0, 0.0);
+		let len = line.buffer.len();
+		line.set_tap_delay(0, 1000.0);
+		assert_eq!(line.buffer.len(), len);
+		assert_eq!(line.taps[0].delay, line.capacity());
+
+		line.reserve(1000.0);
+		line.set_tap_delay(0, 1000.0);
+		assert_eq!(line.taps[0].delay, 1000.0);
+	}
+
+	#[test]
+	fn capacity_accounts_for_the_read_modes () {
+		for mode in 0..3 {
+			let mut line = DelayLine::new(0);
+			line.set_offset(7.0);
+			match mode {
+				1 => line.set_reverse(true),
+				2 => {
+					line.set_interpolation(Interpolation::Sinc(16));
+					line.set_modulation(Lfo::new(1.0, LfoShape::Sine, 0.0, 48000), 3.5);
+				}
+				_ => {}
+			}
+			line.add_tap(1000.0, 1.0, 0.0);
+			let capacity = line.capacity();
+			assert!((1000.0..1001.0).contains(&capacity), "mode {} holds {}", mode, capacity);
+		}
+	}
+
+	#[test]
+	fn buffer_files_delay_like_memory () {
+		let signal: Vec<f32> = (0..64).map(|frame| frame as f32).collect();
+		let mut lines = [DelayLine::new(0), DelayLine::new(0)];
+		let path = std::env::temp_dir().join(format!("jack-delay-test-{}.raw", std::process::id()));
+		lines[1].set_buffer_file(&path).unwrap();
+		assert!(!path.exists());
+		let mut outputs = vec![];
+		for line in &mut lines {
+			line.add_tap(3.0, 1.0, 0.0);
+			line.set_feedback(0.5);
+			let mut output = run(line, &signal[..32]);
+			// growing keeps what was recorded so far
+			line.reserve(1000.0);
+			line.set_tap_delay(0, 20.0);
+			output.extend(run(line, &signal[32..]));
+			outputs.push(output);
+		}
+		assert_eq!(outputs[0], outputs[1]);
+
+		assert!(lines[0].pager().is_none());
+		let pager = lines[1].pager().unwrap();
+		let paging = std::thread::spawn(move || pager.run(16));
+		// the pager stops along with the line
+		drop(lines);
+		paging.join().unwrap();
+	}
+
+	#[test]
+	fn crossfades_delay_changes () {
+		let mut line = DelayLine::new(4);
+		line.add_tap(1.0, 1.0, 0.0);
+		line.reserve(3.0);
+		run(&mut line, &[0.0; 4]);
+		line.set_tap_delay(0, 3.0);
+		// a constant input reads the same through both taps, so the fade is seamless
+		let output = run(&mut line, &[1.0; 8]);
+		assert!(output[3..].iter().all(|&frame| (frame - 1.0).abs() < 1e-6), "{:?}", output);
+	}
+
+	#[test]
+	fn smoothed_values_approach_their_target () {
+		let mut value = Smoothed::new(0.0);
+		value.set(1.0);
+		assert_eq!(value.next(), 1.0);
+		value.set_coefficient(smoothing_coefficient(1.0, 48000));
+		value.set(0.0);
+		let mut previous = 1.0;
+		for _ in 0..480 {
+			let next = value.next();
+			assert!(next < previous);
+			previous = next;
+		}
+		assert!(previous < 0.01);
+	}
+
+	#[test]
+	fn soft_limiter_stays_below_the_ceiling () {
+		let mut limiter = SoftLimiter::new(-1.0, 48000);
+		let ceiling = db_to_gain(-1.0) as Sample;
+		// quiet levels below the knee pass untouched
+		assert_eq!(limiter.next(0.1), 1.0);
+		for &peak in &[0.5, 1.0, 4.0, 100.0] {
+			assert!(peak * limiter.next(peak) <= ceiling);
+		}
+	}
+
+	#[test]
+	fn ducker_lowers_the_gain_while_the_sidechain_is_loud () {
+		let mut ducker = Ducker::new(-20.0, 20.0, 1.0, 10.0, 48000);
+		let mut gains = [0.0; 480];
+		ducker.process(&[1.0; 480], &mut gains);
+		assert!(gains.windows(2).all(|pair| pair[1] <= pair[0]));
+		assert!(gains[479] < 0.11, "{}", gains[479]);
+		let mut gains = [0.0; 4800];
+		ducker.process(&[0.0; 4800], &mut gains);
+		assert!(gains[4799] > 0.99, "{}", gains[4799]);
+	}
+
+	#[test]
+	fn gate_opens_above_the_threshold_only () {
+		let mut gate = Gate::new(-20.0, 1.0, 10.0, 48000);
+		assert!((0..4800).all(|_| gate.next(0.05) == 0.0));
+		let opened = (0..480).map(|_| gate.next(0.5)).last().unwrap();
+		assert!(opened > 0.99, "{}", opened);
+		let closed = (0..4800).map(|_| gate.next(0.0)).last().unwrap();
+		assert!(closed < 0.01, "{}", closed);
+	}
+
+	#[test]
+	fn swell_fades_out_on_transients_and_back_in () {
+		let mut swell = Swell::new(100.0, 48000);
+		assert!((0..4800).all(|_| swell.next(0.0) == 1.0));
+		let gains: Vec<Sample> = (0..14400).map(|_| swell.next(0.5)).collect();
+		assert_eq!(gains[..480].iter().copied().fold(1.0, Sample::min), 0.0);
+		assert_eq!(gains[14399], 1.0);
+	}
+
+	#[test]
+	fn stereo_delay_pans_and_fades_in () {
+		let mut lines = [DelayLine::new(0), DelayLine::new(0)];
+		lines[0].add_tap(1.0, 1.0, 1.0);
+		lines[1].add_tap(1.0, 1.0, -1.0);
+		let mut delay = StereoDelay::new(lines);
+		delay.set_fade_in(2);
+
+		let (mut left, mut right) = ([0.0; 4], [0.0; 4]);
+		delay.process([&[1.0, 0.0, 0.0, 0.0], &[0.0, 1.0, 0.0, 0.0]], [&mut left, &mut right], None, None, None);
+		// the channels swap sides, the repeats fade in from the first one on
+		assert_eq!(right, [0.0, 0.5, 0.0, 0.0]);
+		assert_eq!(left[2], 1.0);
+	}
+
+	#[test]
+	fn repeats_fade_in_once_the_taps_have_filled () {
+		let mut delay = hard_panned([3.0, 3.0]);
+		delay.set_fade_in(4);
+		let (mut left, mut right) = ([0.0; 10], [0.0; 10]);
+		delay.process([&[1.0; 10], &[1.0; 10]], [&mut left, &mut right], None, None, None);
+		// the outputs have faded in long before, the first repeats still rise from silence
+		assert_close(&left, &[0.0, 0.0, 0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 1.0, 1.0]);
+		assert_close(&right, &left);
+	}
+
+	/// A stereo delay with a tap of `delays[channel]` frames kept on the side of its channel.
+	fn hard_panned (delays: [f64; 2]) -> StereoDelay {
+		let mut lines = [DelayLine::new(0), DelayLine::new(0)];
+		for ((line, delay), pan) in lines.iter_mut().zip(delays).zip([-1.0, 1.0]) {
+			line.add_tap(delay, 1.0, pan);
+		}
+		let mut delay = StereoDelay::new(lines);
+		delay.set_fade_in(0);
+		delay
+	}
+
+	/// Compares with a tolerance for the little a hard panned tap leaks to the other side.
+	fn assert_close (actual: &[f32], expected: &[f32]) {
+		assert!(actual.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-6), "{:?} != {:?}", actual, expected);
+	}
+
+	/// Runs one period through `delay` and returns both outputs.
+	fn period (delay: &mut StereoDelay, left: &[f32], right: &[f32]) -> [Vec<f32>; 2] {
+		let mut outputs = [vec![0.0; left.len()], vec![0.0; right.len()]];
+		let [out_1, out_2] = &mut outputs;
+		delay.process([left, right], [out_1, out_2], None, None, None);
+		outputs
+	}
+
+	#[test]
+	fn channels_starting_at_different_times_keep_their_delay () {
+		let mut delay = hard_panned([3.0, 3.0]);
+		// the left channel plays right away, the right one only in the second period
+		let [left, right] = period(&mut delay, &[1.0, 0.0, 0.0, 0.0], &[0.0; 4]);
+		assert_close(&left, &[0.0, 0.0, 0.0, 1.0]);
+		assert_close(&right, &[0.0; 4]);
+		let [left, right] = period(&mut delay, &[0.0; 4], &[0.0, 1.0, 0.0, 0.0]);
+		assert_close(&left, &[0.0; 4]);
+		assert_close(&right, &[0.0, 0.0, 0.0, 0.0]);
+		let [_, right] = period(&mut delay, &[0.0; 4], &[0.0; 4]);
+		assert_close(&right, &[1.0, 0.0, 0.0, 0.0]);
+	}
+
+	#[test]
+	fn channels_with_different_delays_stay_independent () {
+		let mut delay = hard_panned([1.0, 5.0]);
+		let [left, right] = period(&mut delay, &[1.0, 0.0, 0.0, 0.0], &[1.0, 0.0, 0.0, 0.0]);
+		assert_close(&left, &[0.0, 1.0, 0.0, 0.0]);
+		assert_close(&right, &[0.0; 4]);
+		let [left, right] = period(&mut delay, &[0.0; 4], &[0.0; 4]);
+		assert_close(&left, &[0.0; 4]);
+		assert_close(&right, &[0.0, 1.0, 0.0, 0.0]);
+	}
+
+	#[test]
+	fn changing_one_channel_leaves_the_other_alone () {
+		let mut delay = hard_panned([2.0, 2.0]);
+		period(&mut delay, &[1.0, 0.0], &[1.0, 0.0]);
+		delay.lines_mut()[0].set_frozen(true);
+		delay.lines_mut()[1].set_tap_delay(0, 1.0);
+		let [left, right] = period(&mut delay, &[0.0, 0.0, 0.5, 0.0], &[0.0, 0.0, 0.5, 0.0]);
+		// the frozen left channel loops what it had, the right one keeps recording
+		assert_close(&left, &[1.0, 0.0, 1.0, 0.0]);
+		assert_close(&right, &[0.0, 0.0, 0.0, 0.5]);
+	}
+
+	#[test]
+	fn delay_does_not_depend_on_the_period_size () {
+		let input: Vec<f32> = (0..1000).map(|frame| ((frame * 7919) % 1000) as f32 / 1000.0).collect();
+		for &period_size in &[1, 7, 64, 256, 1000] {
+			let mut delay = hard_panned([300.0, 301.0]);
+			let mut outputs = [vec![], vec![]];
+			for chunk in input.chunks(period_size) {
+				let [left, right] = period(&mut delay, chunk, chunk);
+				outputs[0].extend(left);
+				outputs[1].extend(right);
+			}
+			for (channel, frames) in [300, 301].iter().enumerate() {
+				assert_close(&outputs[channel][..*frames], &vec![0.0; *frames]);
+				assert_close(&outputs[channel][*frames..], &input[..1000 - frames]);
+			}
+		}
+	}
+
+	#[test]
+	fn short_buffers_leave_the_rest_silent () {
+		let mut delay = hard_panned([1.0, 1.0]);
+		let (mut left, mut right) = ([9.0; 4], [9.0; 3]);
+		let (mut send_1, mut send_2) = ([9.0; 4], [9.0; 4]);
+		let insert = Insert { sends: [&mut send_1, &mut send_2], returns: [Some(&[0.0]), None] };
+		delay.process([&[1.0, 0.0, 0.0, 0.0], &[1.0, 0.0]], [&mut left, &mut right], Some(&[]), None, Some(insert));
+		assert_close(&left, &[0.0, 1.0, 0.0, 0.0]);
+		assert_close(&right, &[0.0, 1.0, 0.0]);
+		assert_eq!(send_1[2..], [0.0; 2]);
+		assert_eq!(send_2[2..], [0.0; 2]);
+	}
+
+	#[test]
+	fn matrix_delays_each_path_on_its_own () {
+		let mut matrix = DelayMatrix::new(2);
+		matrix.add_path(0, 0, 1.0, 1.0);
+		matrix.add_path(0, 1, 2.5, 0.5);
+		matrix.add_path(1, 1, 0.0, 1.0);
+		matrix.reserve(4);
+		let (mut out_1, mut out_2) = ([0.0; 4], [0.0; 4]);
+		matrix.process([&impulse(4)[..], &[0.0, 0.0, 0.0, 1.0]], [&mut out_1[..], &mut out_2]);
+		assert_eq!(out_1, [0.0, 1.0, 0.0, 0.0]);
+		assert_eq!(out_2, [0.0, 0.0, 0.25, 1.25]);
+		// the paths carry on across periods
+		matrix.process([&[0.0; 4][..], &[0.0; 4]], [&mut out_1[..], &mut out_2]);
+		assert_eq!(out_2, [0.0; 4]);
+	}
+
+	#[test]
+	fn matrix_reads_across_the_end_of_the_rings () {
+		let mut matrix = DelayMatrix::new(1);
+		matrix.add_path(0, 0, 1.5, 2.0);
+		matrix.reserve(4);
+		let input: Vec<f32> = (1..=20).map(|i| i as f32).collect();
+		let mut output = vec![];
+		for period in input.chunks(4) {
+			let mut out = [0.0; 4];
+			matrix.process([period], [&mut out[..]]);
+			output.extend_from_slice(&out);
+		}
+		// half way between the frames one and two frames back, doubled
+		assert_close(&output[..3], &[0.0, 1.0, 3.0]);
+		for (frame, &sample) in output.iter().enumerate().skip(2) {
+			assert_close(&[sample], &[input[frame - 1] + input[frame - 2]]);
+		}
+	}
+
+	#[test]
+	fn matrix_keeps_the_paths_in_step_with_a_short_input () {
+		let mut matrix = DelayMatrix::new(2);
+		matrix.add_path(0, 0, 1.0, 1.0);
+		matrix.add_path(1, 1, 1.0, 1.0);
+		matrix.reserve(4);
+		let (mut out_1, mut out_2) = ([9.0; 4], [9.0; 4]);
+		matrix.process([&[1.0, 2.0, 3.0, 4.0][..], &[1.0, 2.0]], [&mut out_1[..], &mut out_2]);
+		assert_eq!(out_1, [0.0, 1.0, 0.0, 0.0]);
+		assert_eq!(out_2, [0.0, 1.0, 0.0, 0.0]);
+		matrix.process([&[3.0, 4.0, 5.0, 6.0][..], &[3.0, 4.0, 5.0, 6.0]], [&mut out_1[..], &mut out_2]);
+		assert_eq!(out_1, out_2);
+		assert_eq!(out_1, [2.0, 3.0, 4.0, 5.0]);
+	}
+
+	#[test]
+	fn midi_events_keep_their_place_in_the_period () {
+		let mut midi = MidiDelay::new(6, 2, 6);
+		assert!(midi.push(1, &[0x90, 60, 100]));
+		assert!(midi.push(3, &[0x80, 60, 0]));
+		assert!(!midi.push(3, &[0xf8]));
+		let mut sent = vec![];
+		for _ in 0..3 {
+			midi.release(4, |time, bytes| sent.push((time, bytes.to_vec())));
+			sent.push((usize::MAX, vec![]));
+		}
+		assert_eq!(sent, [
+			(usize::MAX, vec![]),
+			(3, vec![0x90, 60, 100]),
+			(usize::MAX, vec![]),
+			(1, vec![0x80, 60, 0]),
+			(usize::MAX, vec![]),
+		]);
+		// a shorter delay doesn't overtake what is still queued
+		midi.set_delay(0);
+		assert!(midi.push(0, &[0xf8]));
+		assert!(midi.push(2, &[0xfa]));
+		midi.release(4, |time, bytes| sent.push((time, bytes.to_vec())));
+		assert_eq!(sent[5..], [(0, vec![0xf8]), (2, vec![0xfa])]);
+	}
+
+	#[test]
+	fn cv_moves_the_taps () {
+		let mut delay = hard_panned([2.0, 2.0]);
+		for line in delay.lines_mut() {
+			line.set_cv(2.0, 1.0);
+		}
+		let (mut left, mut right) = ([0.0; 8], [0.0; 8]);
+		// clamped to 1, so the tap moves by the offset plus the depth
+		delay.process([&impulse(8), &[0.0; 8]], [&mut left, &mut right], None, Some(&[3.0; 8]), None);
+		assert_close(&left, &[0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+		delay.process([&impulse(8), &[0.0; 8]], [&mut left, &mut right], None, Some(&[-0.5; 8]), None);
+		assert_close(&left, &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+	}
+
+	#[test]
+	fn drift_resampler_passes_a_steady_stream_through () {
+		let mut resampler = DriftResampler::new(48000, 48000, 4);
+		let mut queue: VecDeque<[f32; 2]> = (1..=8).map(|n| [n as f32, -n as f32]).collect();
+		let (mut left, mut right) = ([0.0; 4], [0.0; 4]);
+		resampler.process(queue.len() - 4, [&mut left, &mut right], || queue.pop_front());
+		// one frame late for the interpolation
+		assert_eq!(left, [0.0, 1.0, 2.0, 3.0]);
+		assert_eq!(right, [0.0, -1.0, -2.0, -3.0]);
+		assert_eq!(resampler.ratio(), 1.0);
+	}
+
+	#[test]
+	fn drift_resampler_follows_the_fill_of_the_queue () {
+		let mut resampler = DriftResampler::new(48000, 48000, 256);
+		let (mut left, mut right) = ([0.0; 64], [0.0; 64]);
+		resampler.process(400, [&mut left, &mut right], || Some([0.0; 2]));
+		assert!(resampler.ratio() > 1.0);
+		// a queue far too full is skipped down to the target
+		let mut queued = 10_000;
+		resampler.process(queued, [&mut left, &mut right], || {
+			queued -= 1;
+			Some([0.0; 2])
+		});
+		assert!((10_000 - queued) > 9_000);
+	}
+
+	#[test]
+	fn stereo_delay_bypasses_to_the_input () {
+		let mut lines = [DelayLine::new(0), DelayLine::new(0)];
+		for line in &mut lines {
+			line.add_tap(2.0, 1.0, 0.0);
+		}
+		let mut delay = StereoDelay::new(lines);
+		delay.set_fade_in(0);
+		delay.set_bypassed(true, 1);
+
+		let input = [1.0, 0.5, 0.25];
+		let (mut left, mut right) = ([0.0; 3], [0.0; 3]);
+		delay.process([&input, &input], [&mut left, &mut right], None, None, None);
+		assert_eq!(left, input);
+		assert_eq!(right, input);
+	}
+
+	#[test]
+	fn mid_side_delays_the_sum_and_the_difference () {
+		let mut delay = hard_panned([1.0, 3.0]);
+		delay.set_mid_side(true);
+		let [left, right] = period(&mut delay, &[1.0, 0.0, 0.0, 0.0, 0.0], &[0.0; 5]);
+		// the mid comes back on both sides, the side with opposite polarity
+		assert_close(&left, &[0.0, 0.5, 0.0, 0.5, 0.0]);
+		assert_close(&right, &[0.0, 0.5, 0.0, -0.5, 0.0]);
+	}
+
+	#[test]
+	fn swap_moves_the_repeats_to_the_opposite_side () {
+		let mut delay = hard_panned([1.0, 1.0]);
+		delay.set_swap(1.0);
+		let [left, right] = period(&mut delay, &[1.0, 0.0, 0.0], &[0.0; 3]);
+		assert_close(&left, &[0.0; 3]);
+		assert_close(&right, &[0.0, 1.0, 0.0]);
+
+		let mut delay = hard_panned([1.0, 1.0]);
+		delay.set_swap(0.5);
+		let [left, right] = period(&mut delay, &[1.0, 0.0, 0.0], &[0.0; 3]);
+		assert_close(&left, &[0.0, 0.5, 0.0]);
+		assert_close(&right, &left);
+	}
+
+	#[test]
+	fn cross_feedback_bounces_between_the_channels () {
+		let mut delay = hard_panned([2.0, 2.0]);
+		for line in delay.lines_mut() {
+			line.set_cross_feedback(0.5);
+		}
+		let [left, right] = period(&mut delay, &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], &[0.0; 9]);
+		assert_close(&left, &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.25, 0.0, 0.0]);
+		assert_close(&right, &[0.0, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.125]);
+	}
+
+	#[test]
+	fn gate_keeps_quiet_input_out_of_the_lines () {
+		let mut delay = hard_panned([2.0, 2.0]);
+		delay.set_gate(Some(Gate::new(-20.0, 0.0, 0.0, 48000)));
+		let [left, _] = period(&mut delay, &[0.05, 0.05, 0.0, 0.0], &[0.0; 4]);
+		assert_close(&left, &[0.0; 4]);
+		let [left, _] = period(&mut delay, &[0.5, 0.0, 0.0, 0.0], &[0.0; 4]);
+		assert_close(&left, &[0.0, 0.0, 0.5, 0.0]);
+	}
+
+	#[test]
+	fn limiter_reports_its_lowest_gain () {
+		let mut delay = hard_panned([1.0, 1.0]);
+		assert_eq!(delay.take_limiter_gain(), None);
+		delay.set_limiter(Some(SoftLimiter::new(-6.0, 48000)));
+		let [left, right] = period(&mut delay, &[0.0, 2.0, 0.0, 0.0], &[0.0, 2.0, 0.0, 0.0]);
+		assert!(left.iter().chain(&right).all(|frame| frame.abs() < db_to_gain(-6.0)));
+		let gain = delay.take_limiter_gain().unwrap();
+		assert!(gain < db_to_gain(-6.0) / 2.0, "{}", gain);
+		// taken, it starts over at unity
+		assert_eq!(delay.take_limiter_gain(), Some(1.0));
+	}
+}
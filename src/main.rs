@@ -1,48 +1,2469 @@
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
-use jack::{AsyncClient,ProcessHandler};
+use jack::ProcessHandler;
+use jack_delay::{
+	db_to_gain, millis_to_frames, smoothing_coefficient, speed_of_sound,
+	BandSplitter, ChangeMode, DelayLine, DelayMatrix, DelayTime, DriftResampler, Ducker, Gate, Insert, Interpolation,
+	Lfo, LfoShape, MidiDelay, Sample, SoftLimiter, StereoDelay, Swell, Tempo, Timebase, WowFlutter,
+};
 
 #[derive(StructOpt)]
 #[structopt(about = "A cli application that applies the specified amount of delay between two input and two output ports.")]
 struct Args {
-	#[structopt(short = "n", help = "Number of frames to delay the signal by")]
-	delay_frames: usize,
+	#[structopt(short = "n", allow_hyphen_values = true, required_unless_one = &["delay", "distance", "video-frames", "taps", "haas", "units", "matrix"], conflicts_with_all = &["delay", "distance", "video-frames"], help = "Number of frames to delay the signal by, may be fractional (e.g. 123.4)")]
+	delay_frames: Option<f64>,
+	#[structopt(short, long, allow_hyphen_values = true, conflicts_with_all = &["distance", "video-frames"], help = "Delay with a unit, e.g. 120ms, 0.5s, 1/4 or 12.5m (plain numbers are frames), negative delays are reported as latency compensation")]
+	delay: Option<DelayTime>,
+	#[structopt(long, parse(try_from_str = parse_distance), help = "Delay by the time sound travels this distance, e.g. 12.5m or 41ft (plain numbers are meters)")]
+	distance: Option<DelayTime>,
+	#[structopt(long, conflicts_with = "distance", requires = "fps", help = "Delay by this many video frames for lip-sync, e.g. 3 with --fps 25")]
+	video_frames: Option<f64>,
+	#[structopt(long, parse(try_from_str = parse_positive), help = "Frame rate of the video, e.g. 25 or 29.97")]
+	fps: Option<f32>,
+	#[structopt(long, help = "Delay of the first tap on the right channel (the side channel with --ms) if it should differ from the left, e.g. 1/8d")]
+	delay_right: Option<DelayTime>,
+	#[structopt(long, default_value = "20C", allow_hyphen_values = true, parse(try_from_str = parse_temperature), help = "Air temperature for distance based delays, e.g. 28C or 82F")]
+	temperature: f64,
+	#[structopt(long, default_value = "0", help = "Relative humidity in percent for distance based delays")]
+	humidity: f64,
+	#[structopt(long = "tap", number_of_values = 1, help = "Additional tap as <delay>:<gain>[:<pan>], e.g. 250ms:0.8 or 1/8:0.5:-1 (may be repeated)")]
+	taps: Vec<TapSpec>,
+	#[structopt(long, parse(try_from_str = parse_fraction), help = "Fan the taps without a pan across the stereo field, from 0.0 (centered) to 1.0 (hard left to hard right)")]
+	spread: Option<f32>,
+	#[structopt(long, alias = "crossfade", default_value = "10", parse(try_from_str = parse_glide), help = "Milliseconds over which delay changes are crossfaded or repitched, 0 for instant changes")]
+	glide: f64,
+	#[structopt(long, default_value = "5", help = "Milliseconds to crossfade over when toggling bypass")]
+	bypass_fade: f64,
+	#[structopt(long, default_value = "20", help = "Milliseconds over which the outputs fade in from silence after starting, and the repeats once the delay has filled")]
+	fade_in: f64,
+	#[structopt(long, default_value = "20", help = "Milliseconds over which gains, mix and feedback follow changes at runtime")]
+	smoothing: f64,
+	#[structopt(long = "ms", help = "Delay the mid and side signal instead of left and right, e.g. -d 0 --delay-right 15ms for a side only slap")]
+	mid_side: bool,
+	#[structopt(long, help = "Start with commands for one channel affecting only that channel (see link)")]
+	unlinked: bool,
+	#[structopt(long, default_value = "crossfade", help = "How delay changes are applied: crossfade or repitch (tape style glide)")]
+	change_mode: ChangeMode,
+	#[structopt(short, long, default_value = "0", parse(try_from_str = parse_fraction), help = "Amount of the delayed signal fed back into the delay line (0.0 - 1.0)")]
+	feedback: f32,
+	#[structopt(long, parse(try_from_str = parse_fraction), help = "Feedback of the right channel (the side channel with --ms) if it should differ from the left")]
+	feedback_right: Option<f32>,
+	#[structopt(long, default_value = "0", parse(try_from_str = parse_fraction), help = "Amount of each channel's delayed signal fed into the other channel (0.0 - 1.0)")]
+	cross_feedback: f32,
+	#[structopt(short, long, default_value = "1", parse(try_from_str = parse_fraction), help = "Balance between the dry input (0.0) and the delayed signal (1.0)")]
+	mix: f32,
+	#[structopt(long, default_value = "0", allow_hyphen_values = true, help = "Gain in dB applied to the inputs")]
+	input_gain: f32,
+	#[structopt(long, default_value = "0", allow_hyphen_values = true, help = "Gain in dB applied to the outputs")]
+	output_gain: f32,
+	#[structopt(long, allow_hyphen_values = true, help = "Ceiling in dBFS of a soft limiter on the outputs, e.g. -1")]
+	limit: Option<f32>,
+	#[structopt(long, help = "Cutoff in Hz of a low-pass filter darkening each repeat")]
+	fb_lowpass: Option<f64>,
+	#[structopt(long, help = "Cutoff in Hz of a high-pass filter thinning out each repeat")]
+	fb_highpass: Option<f64>,
+	#[structopt(long, help = "Cutoff in Hz of a low-pass filter on the delayed signal sent to the outputs")]
+	wet_lowpass: Option<f64>,
+	#[structopt(long, help = "Cutoff in Hz of a high-pass filter on the delayed signal sent to the outputs")]
+	wet_highpass: Option<f64>,
+	#[structopt(long, help = "Remove dc offsets from the inputs before they enter the delay line")]
+	dc_block: bool,
+	#[structopt(long, parse(try_from_str = parse_positive), help = "Drive of a soft saturation stage in the feedback path, higher values limit the repeats earlier")]
+	saturation: Option<f32>,
+	#[structopt(long, parse(try_from_str = parse_bits), help = "Bit depth the delayed signal is stored at (1 - 24), for a lo-fi digital delay")]
+	crush_bits: Option<u32>,
+	#[structopt(long, parse(try_from_str = parse_positive), help = "Sample rate in Hz the delayed signal is stored at, for a lo-fi digital delay")]
+	crush_rate: Option<f32>,
+	#[structopt(long, default_value = "0", allow_hyphen_values = true, help = "Level in dBFS the recirculating signal is limited to, so runaway feedback can't get any louder")]
+	feedback_ceiling: f32,
+	#[structopt(long, allow_hyphen_values = true, parse(try_from_str = parse_shimmer), help = "Pitch shift in semitones applied to every repeat (-12 - 12), e.g. 12 for a shimmer climbing an octave per pass")]
+	shimmer: Option<f64>,
+	#[structopt(long, help = "Level in dBFS above which the sidechain input ducks the delayed signal, registers the sidechain port")]
+	duck_threshold: Option<f32>,
+	#[structopt(long, default_value = "20", help = "Gain reduction in dB applied to the delayed signal while ducking")]
+	duck_depth: f32,
+	#[structopt(long, default_value = "10", help = "Milliseconds for the ducking to engage")]
+	duck_attack: f64,
+	#[structopt(long, default_value = "300", help = "Milliseconds for the delayed signal to recover after ducking")]
+	duck_release: f64,
+	#[structopt(long, help = "Fade the delayed signal out on transients of the input and back in over this many milliseconds")]
+	swell: Option<f64>,
+	#[structopt(long, allow_hyphen_values = true, help = "Level in dBFS below which the input is kept out of the delay line")]
+	gate_threshold: Option<f32>,
+	#[structopt(long, default_value = "1", help = "Milliseconds for the gate to open")]
+	gate_attack: f64,
+	#[structopt(long, default_value = "100", help = "Milliseconds for the gate to close")]
+	gate_release: f64,
+	#[structopt(long, parse(try_from_str = parse_haas), conflicts_with = "mono", help = "Delay only the right channel by an additional 1 - 35 milliseconds for stereo widening")]
+	haas: Option<f64>,
+	#[structopt(long, help = "Invert the polarity of the delayed left channel")]
+	invert_left: bool,
+	#[structopt(long, help = "Invert the polarity of the delayed right channel")]
+	invert_right: bool,
+	#[structopt(long, parse(try_from_str = parse_heads), help = "Tape echo with three playback heads at one, two and three times the delay, engaged by mode 1 - 7 (1 - 3 single heads, 4: 2+3, 5: 1+2, 6: 1+3, 7: all)")]
+	heads: Option<usize>,
+	#[structopt(long, default_value = "0", parse(try_from_str = parse_fraction), help = "Share of each delayed channel sent to the opposite output instead of its own, 1.0 swaps them completely")]
+	swap: f32,
+	#[structopt(long, help = "Keep the input out of the delay except while thrown in with the throw command or --throw-note")]
+	throw: bool,
+	#[structopt(long, help = "Register fb_send1/2 and fb_return1/2 ports to patch external processing into the feedback path")]
+	feedback_insert: bool,
+	#[structopt(long, help = "Register dry1 and dry2 output ports carrying the unprocessed inputs")]
+	dry_outputs: bool,
+	#[structopt(long, help = "Register monitor1 and monitor2 output ports starting on the dry, wet or difference (dry minus wet) signal, switched with the monitor command")]
+	monitor: Option<MonitorSource>,
+	#[structopt(long, conflicts_with_all = &["dry-outputs", "feedback-insert", "mid-side", "monitor"], help = "Register just in1 and out1 and delay a single channel")]
+	mono: bool,
+	#[structopt(long = "unit", number_of_values = 1, conflicts_with_all = &["delay-frames", "delay", "distance", "video-frames", "delay-right", "dry-outputs", "feedback-insert", "monitor"], help = "Delay unit with its own ports as <name>:<delay>, e.g. front:12ms, all other settings apply to every unit (may be repeated)")]
+	units: Vec<UnitSpec>,
+	#[structopt(long, parse(try_from_str = Routing::load), conflicts_with_all = &["delay-frames", "delay", "distance", "video-frames", "taps", "haas", "units", "mono", "port-names", "dry-outputs", "feedback-insert", "monitor", "duck-threshold"], help = "File of \"<input> <output> <delay> [<gain dB>]\" lines routing numbered inputs to outputs with a delay each, in place of the delay")]
+	matrix: Option<Routing>,
+	#[structopt(long, help = "Names of the channels used in the port names instead of 1 and 2, e.g. mainL,mainR for in_mainL, out_mainL, ...")]
+	port_names: Option<ChannelNames>,
+	#[structopt(long, help = "Prefix of all port names, e.g. zone1_ to tell several instances apart in a patchbay")]
+	port_prefix: Option<String>,
+	#[structopt(long, help = "Connect the inputs to the first physical capture ports and the outputs to the first physical playback ports")]
+	connect: bool,
+	#[structopt(long, use_delimiter = true, help = "Ports to connect the inputs to, as regular expressions also matching ports that appear later, e.g. system:capture_1,system:capture_2")]
+	connect_in: Vec<String>,
+	#[structopt(long, use_delimiter = true, help = "Ports to connect the outputs to, as regular expressions also matching ports that appear later, e.g. 'ardour:LTC.*'")]
+	connect_out: Vec<String>,
+	#[structopt(long = "crossover", number_of_values = 1, help = "Frequency in Hz splitting the input into bands that are delayed separately (may be given twice for three bands)")]
+	crossovers: Vec<f64>,
+	#[structopt(long = "band-delay", number_of_values = 1, help = "Delay of a band in front of the delay line, lowest band first, one more than crossovers")]
+	band_delays: Vec<DelayTime>,
+	#[structopt(long, default_value = "linear", help = "Interpolation of fractional delays: linear, allpass or sinc:<points> (e.g. sinc:32)")]
+	interp: Interpolation,
+	#[structopt(long, help = "Play every delay window back reversed, like a reverse tape echo")]
+	reverse: bool,
+	#[structopt(long, conflicts_with = "reverse", help = "Slice the delay into grains of this many milliseconds and play them back in shuffled order")]
+	shuffle: Option<f64>,
+	#[structopt(long, help = "Let every repeat land up to this many milliseconds late, picked at random for each repeat")]
+	humanize: Option<f64>,
+	#[structopt(long, help = "Depth in milliseconds of a slow random drift of the delay, like a worn tape transport")]
+	wow: Option<f64>,
+	#[structopt(long, help = "Depth in milliseconds of a fast random wobble of the delay, like an uneven capstan")]
+	flutter: Option<f64>,
+	#[structopt(long, default_value = "0", help = "Rate of the delay time modulation in Hz")]
+	mod_rate: f64,
+	#[structopt(long, default_value = "0", help = "Depth of the delay time modulation in milliseconds, for chorus/flanger effects")]
+	mod_depth: f64,
+	#[structopt(long, default_value = "sine", help = "Waveform of the delay time modulation: sine, triangle, ramp or random")]
+	mod_shape: LfoShape,
+	#[structopt(long, default_value = "0", help = "Phase offset in degrees of the right channel's modulation")]
+	mod_phase: f64,
+	#[structopt(long, conflicts_with_all = &["units", "matrix"], help = "Register a cv input whose signal from -1 to 1 modulates the delay, e.g. from a modular synth client")]
+	cv: bool,
+	#[structopt(long, default_value = "10", allow_hyphen_values = true, help = "Milliseconds the delay moves by at a cv of 1, negative to shorten it")]
+	cv_depth: f64,
+	#[structopt(long, default_value = "0", allow_hyphen_values = true, help = "Milliseconds the delay moves by at a cv of 0")]
+	cv_offset: f64,
+	#[structopt(long, help = "Round tapped delays to the nearest note value at the current tempo")]
+	tap_round: bool,
+	#[structopt(long, parse(try_from_str = Automation::load), help = "File of \"<seconds> <delay>\" lines setting the delay of the first tap at those JACK transport positions")]
+	automation: Option<Automation>,
+	#[structopt(long, help = "Only run the delay while the JACK transport rolls, the tails are faded out and dropped when it stops")]
+	transport_gate: bool,
+	#[structopt(long, conflicts_with_all = &["units", "matrix"], help = "Register midi_in and midi_out ports and delay MIDI events along with the audio")]
+	midi: bool,
+	#[structopt(long, requires = "midi", help = "Delay of the MIDI events with a unit like --delay, by default they follow the delay of the first channel")]
+	midi_delay: Option<DelayTime>,
+	#[structopt(long, requires_all = &["midi", "throw"], parse(try_from_str = parse_note), help = "MIDI note 0 - 127 that throws the input in for as long as it is held on midi_in, the note itself isn't passed on")]
+	throw_note: Option<u8>,
+	#[structopt(long, default_value = "120", help = "Tempo for note valued delays while the JACK transport provides none")]
+	bpm: f64,
+	#[structopt(long, help = "Longest delay to allow changing to at runtime, allocated at startup")]
+	max_delay: Option<DelayTime>,
+	#[structopt(long, default_value = "1024", help = "Most memory in MiB the delay buffers may take up")]
+	max_memory: f64,
+	#[structopt(long, parse(from_os_str), help = "Directory to keep the delay buffers in instead of RAM, for delays of minutes")]
+	disk_buffer: Option<std::path::PathBuf>,
+	#[structopt(long, conflicts_with_all = &["dry-outputs", "feedback-insert", "monitor"], help = "Threads that take a share of the units or the matrix outputs off the process thread, for high channel counts")]
+	workers: Option<usize>,
+	#[structopt(long, default_value = "5", help = "Seconds without a process callback after which the client gives up, 0 waits forever")]
+	watchdog: f64,
+	#[structopt(long, default_value = "10", help = "Seconds after which to warn that none of the ports is connected, 0 never warns")]
+	unused_after: f64,
+	#[structopt(long, help = "Exit when none of the ports has been connected for --unused-after seconds instead of warning")]
+	exit_if_unused: bool,
+	#[structopt(long, help = "Report the delay of the first tap as latency of the outputs, so latency aware hosts compensate for it")]
+	report_latency: bool,
+	#[structopt(long = "latency-range", number_of_values = 1, allow_hyphen_values = true, requires = "report-latency", help = "Least and most latency <min>:<max> to report for the outputs instead of the range the modulation covers, given again for the right channel")]
+	latency_ranges: Vec<LatencyRange>,
+	#[structopt(long, help = "Start the JACK server if it isn't running yet instead of exiting")]
+	start_server: bool,
+	#[structopt(long, help = "Name of the JACK server to connect to instead of the default one")]
+	server: Option<String>,
+	#[structopt(long, conflicts_with_all = &["units", "matrix"], help = "Name of a second JACK server to play the delayed outputs on as well, resampled to its clock")]
+	bridge: Option<String>,
+	#[structopt(long, help = "File to restore the connections of the ports from on starting and to save them to on exit and with the save command")]
+	connections: Option<std::path::PathBuf>,
+	#[structopt(long, help = "Wait for the JACK server to come back after it shut down and carry on with the same settings and connections")]
+	reconnect: bool,
 	#[structopt(short, parse(from_occurrences), help = "Verbosity (-vv for maximum output)")]
 	verbosity: u8,
 }
 
-/// Pushes received frames into memory vector
-fn receive_frames (in_port: &[f32], memory: &mut Vec<f32>) {
-	for frame in in_port {
-		memory.push(*frame);
+impl Args {
+	fn channels (&self) -> usize {
+		if self.mono { 1 } else { 2 }
+	}
+
+	fn port_names (&self) -> PortNames {
+		PortNames { prefix: self.port_prefix.clone().unwrap_or_default(), channels: self.port_names.clone() }
+	}
+
+	/// The requested main delay, if any.
+	fn delay (&self) -> Option<DelayTime> {
+		match (self.delay_frames, self.delay) {
+			(Some(frames), _) => Some(DelayTime::Frames(frames)),
+			// the haas offset alone still needs a tap to offset
+			(None, None) if self.distance.is_some() => self.distance,
+			(None, None) if self.video_frames.is_some() => self.video_frames
+				.zip(self.fps)
+				.map(|(frames, fps)| DelayTime::Seconds(frames / fps as f64)),
+			(None, None) if self.haas.is_some() => Some(DelayTime::Frames(0.0)),
+			(None, delay) => delay,
+		}
+	}
+
+	/// Port names and main delay of each unit, a single one with the
+	/// delay given by the delay options unless there are `--unit`s.
+	fn unit_setups (&self) -> Vec<(PortNames, Option<DelayTime>)> {
+		if self.units.is_empty() {
+			return vec![(self.port_names(), self.delay())];
+		}
+		self.units.iter()
+			.map(|unit| {
+				let mut names = self.port_names();
+				names.prefix = format!("{}{}_", names.prefix, unit.name);
+				(names, Some(unit.delay))
+			})
+			.collect()
+	}
+
+	/// All taps of a channel as (delay, gain, pan), `main` comes first at unity gain.
+	/// Taps without a pan are fanned out by `--spread` or stay on their own channel.
+	fn taps (&self, main: Option<DelayTime>, channel: usize) -> Vec<(DelayTime, f32, f32)> {
+		let mut taps: Vec<TapSpec> = main
+			.map(|time| TapSpec { time, gain: 1.0, pan: None })
+			.into_iter()
+			.chain(self.taps.iter().copied())
+			.collect();
+		if let (1, Some(delay), Some(first)) = (channel, self.delay_right, taps.first_mut()) {
+			first.time = delay;
+		}
+		if let (Some(mode), Some(&first)) = (self.heads, taps.first()) {
+			// the first tap becomes the spacing of the heads
+			let heads = (0..HEADS).map(|head| TapSpec {
+				time: first.time.scaled((head + 1) as f64),
+				gain: head_gains(mode)[head],
+				pan: first.pan,
+			});
+			taps.splice(0..1, heads);
+		}
+		let count = taps.len();
+		let own_side = if channel == 0 { -1.0 } else { 1.0 };
+		taps.iter()
+			.enumerate()
+			.map(|(index, tap)| {
+				let pan = match (tap.pan, self.spread) {
+					(Some(pan), _) => pan,
+					(None, Some(spread)) if count > 1 => spread * (2.0 * index as f32 / (count - 1) as f32 - 1.0),
+					_ => own_side,
+				};
+				(tap.time, tap.gain, pan)
+			})
+			.collect()
+	}
+
+	/// The taps of both channels of each unit.
+	fn unit_taps (&self) -> Vec<[Vec<(DelayTime, f32, f32)>; 2]> {
+		self.unit_setups()
+			.iter()
+			.map(|&(_, delay)| if self.mono {
+				// every tap ends up on out1, the second line stays empty and takes up no memory
+				[self.taps(delay, 0).into_iter().map(|(delay, gain, _)| (delay, gain, -1.0)).collect(), vec![]]
+			} else {
+				[self.taps(delay, 0), self.taps(delay, 1)]
+			})
+			.collect()
+	}
+
+	/// Checks what clap can't check on its own. Without the `sample_rate` of the server the
+	/// parts depending on it are checked for the whole range servers run at, so a command
+	/// line can be tried without one and is checked again once connected.
+	fn validate (&self, sample_rate: Option<usize>) -> Result<(), String> {
+		if !self.band_delays.is_empty() || !self.crossovers.is_empty() {
+			let nyquist = sample_rate.unwrap_or(HIGHEST_SAMPLE_RATE) as f64 / 2.0;
+			if self.crossovers.len() > 2 {
+				return Err("at most two crossovers are supported".to_string());
+			} else if self.band_delays.len() != self.crossovers.len() + 1 {
+				return Err("one more band delay than crossovers is needed".to_string());
+			} else if self.crossovers.windows(2).any(|pair| pair[0] >= pair[1]) {
+				return Err("crossovers have to be given in ascending order".to_string());
+			} else if let Some(crossover) = self.crossovers.iter().find(|&&crossover| !(crossover > 0.0 && crossover < nyquist)) {
+				let rate = if sample_rate.is_some() { "the" } else { "the highest" };
+				return Err(format!("the crossover at {} Hz has to lie above 0 Hz and below {} Hz, half {} sample rate", crossover, nyquist, rate));
+			}
+		}
+		let channels = self.channels();
+		if let Some(ChannelNames(names)) = &self.port_names {
+			if names.len() != channels {
+				return Err(format!("--port-names needs a name for each of the {} channel(s)", channels));
+			}
+		}
+		// delays in time units take up the least memory at the lowest rate
+		self.check_memory(sample_rate.unwrap_or(LOWEST_SAMPLE_RATE))
+	}
+
+	/// Refuses delays that don't fit into `--max-memory` at `sample_rate`, following the sizing of the lines.
+	fn check_memory (&self, sample_rate: usize) -> Result<(), String> {
+		let timebase = Timebase {
+			sample_rate,
+			tempo: Tempo { bpm: self.bpm, beat_type: 4.0 },
+			speed_of_sound: speed_of_sound(self.temperature, self.humidity),
+		};
+		if let Some(routing) = &self.matrix {
+			let longest = routing.paths.iter().map(|&(_, _, delay, _)| delay.to_frames(&timebase)).fold(0.0, f64::max);
+			let inputs = routing.inputs();
+			let mebibytes = longest * inputs as f64 * std::mem::size_of::<Sample>() as f64 / (1024.0 * 1024.0);
+			if mebibytes.is_nan() || mebibytes > self.max_memory {
+				return Err(format!(
+					"a delay of {:.0} frames at {} Hz needs {:.0} MiB for {} input(s), more than --max-memory {} MiB",
+					longest.ceil(), sample_rate, mebibytes, inputs, self.max_memory
+				));
+			}
+			return Ok(());
+		}
+		// a buffer on disk is only limited by the space there
+		if self.disk_buffer.is_some() {
+			return Ok(());
+		}
+		let taps = self.unit_taps();
+		let compensation = taps.iter()
+			.flatten()
+			.flatten()
+			.map(|&(delay, _, _)| -delay.to_frames(&timebase))
+			.fold(0.0, f64::max)
+			.ceil();
+		let haas = self.haas.map_or(0.0, |haas| millis_to_frames(haas, sample_rate));
+		let heads = if self.heads.is_some() { HEADS } else { 1 };
+		let automated = self.automation.iter()
+			.flat_map(|automation| &automation.breakpoints)
+			.map(|&(_, delay)| delay.scaled(heads as f64));
+		let longest = taps.iter()
+			.flatten()
+			.flatten()
+			.map(|&(delay, _, _)| delay)
+			.chain(automated)
+			.chain(self.max_delay)
+			.map(|delay| delay.to_frames(&timebase))
+			.fold(0.0, f64::max) + compensation + haas;
+		let cv_range = self.cv.then(|| self.cv_depth.abs() + self.cv_offset.abs());
+		let modulation: f64 = [Some(self.mod_depth), self.humanize, self.wow, self.flutter, cv_range]
+			.iter()
+			.flatten()
+			.map(|&ms| millis_to_frames(ms, sample_rate))
+			.sum();
+		let frames = if self.reverse || self.shuffle.is_some() { longest * 2.0 } else { longest + modulation };
+		let buffers = self.channels() * taps.len();
+		let mebibytes = frames * buffers as f64 * std::mem::size_of::<Sample>() as f64 / (1024.0 * 1024.0);
+		if mebibytes.is_nan() || mebibytes > self.max_memory {
+			return Err(format!(
+				"a delay of {:.0} frames at {} Hz needs {:.0} MiB for {} channel(s), more than --max-memory {} MiB",
+				frames.ceil(), sample_rate, mebibytes, buffers, self.max_memory
+			));
+		}
+		Ok(())
+	}
+}
+
+/// Parses a value in the range 0.0 - 1.0.
+fn parse_fraction (s: &str) -> Result<f32, String> {
+	match s.parse::<f32>() {
+		Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+		_ => Err(format!("expected a value between 0.0 and 1.0, got \"{}\"", s)),
+	}
+}
+
+/// Parses the haas offset in milliseconds.
+fn parse_haas (s: &str) -> Result<f64, String> {
+	match s.parse::<f64>() {
+		Ok(ms) if (1.0..=35.0).contains(&ms) => Ok(ms),
+		_ => Err(format!("expected 1 - 35 milliseconds, got \"{}\"", s)),
+	}
+}
+
+/// Parses the pitch shift of the feedback path in semitones.
+fn parse_shimmer (s: &str) -> Result<f64, String> {
+	match s.parse::<f64>() {
+		Ok(semitones) if (-12.0..=12.0).contains(&semitones) => Ok(semitones),
+		_ => Err(format!("expected -12 - 12 semitones, got \"{}\"", s)),
+	}
+}
+
+/// Number of playback heads in tape echo mode.
+const HEADS: usize = 3;
+
+/// Heads engaged in each position of the mode switch, as on the classic space echo.
+const HEAD_MODES: [[bool; HEADS]; 7] = [
+	[true, false, false],
+	[false, true, false],
+	[false, false, true],
+	[false, true, true],
+	[true, true, false],
+	[true, false, true],
+	[true, true, true],
+];
+
+/// Parses a position of the head mode switch, 1 - 7.
+fn parse_heads (s: &str) -> Result<usize, String> {
+	match s.parse::<usize>() {
+		Ok(mode) if (1..=HEAD_MODES.len()).contains(&mode) => Ok(mode),
+		_ => Err(format!("expected a head mode from 1 to {}, got \"{}\"", HEAD_MODES.len(), s)),
+	}
+}
+
+/// Parses the number of a MIDI note, 0 - 127.
+fn parse_note (s: &str) -> Result<u8, String> {
+	match s.parse::<u8>() {
+		Ok(note) if note < 128 => Ok(note),
+		_ => Err(format!("expected a MIDI note from 0 to 127, got \"{}\"", s)),
+	}
+}
+
+/// Gains of the playback heads in head `mode`.
+fn head_gains (mode: usize) -> [f32; HEADS] {
+	HEAD_MODES[mode - 1].map(|engaged| if engaged { 1.0 } else { 0.0 })
+}
+
+/// Parses the bit depth of the bit crusher.
+fn parse_bits (s: &str) -> Result<u32, String> {
+	match s.parse::<u32>() {
+		Ok(bits) if (1..=24).contains(&bits) => Ok(bits),
+		_ => Err(format!("expected 1 - 24 bits, got \"{}\"", s)),
+	}
+}
+
+/// Parses a switch from the control interface.
+fn parse_toggle (s: &str) -> Result<bool, String> {
+	match s {
+		"on" => Ok(true),
+		"off" => Ok(false),
+		_ => Err(format!("expected on or off, got \"{}\"", s)),
+	}
+}
+
+/// Parses a glide time in milliseconds, an optional `ms` suffix is accepted.
+fn parse_glide (s: &str) -> Result<f64, String> {
+	match s.trim_end_matches("ms").parse::<f64>() {
+		Ok(ms) if ms >= 0.0 => Ok(ms),
+		_ => Err(format!("expected a glide time in milliseconds, got \"{}\"", s)),
+	}
+}
+
+/// Parses a value greater than zero.
+fn parse_positive (s: &str) -> Result<f32, String> {
+	match s.parse::<f32>() {
+		Ok(value) if value > 0.0 => Ok(value),
+		_ => Err(format!("expected a value greater than 0, got \"{}\"", s)),
+	}
+}
+
+/// Parses a gain in dB, an optional `dB` suffix is accepted.
+fn parse_gain (s: &str) -> Result<f32, String> {
+	s.trim_end_matches("dB")
+		.trim_end_matches("db")
+		.parse()
+		.map_err(|_| format!("expected a gain in dB, got \"{}\"", s))
+}
+
+/// Parses a temperature such as 28C or 82F into °C, plain numbers are °C.
+fn parse_temperature (s: &str) -> Result<f64, String> {
+	let s = s.trim();
+	let invalid = || format!("invalid temperature \"{}\", expected e.g. 28C or 82F", s);
+	if let Some(fahrenheit) = s.strip_suffix('F') {
+		fahrenheit.parse::<f64>().map(|f| (f - 32.0) * 5.0 / 9.0).map_err(|_| invalid())
+	} else {
+		s.strip_suffix('C').unwrap_or(s).parse().map_err(|_| invalid())
+	}
+}
+
+/// Parses a distance, plain numbers are taken as meters.
+fn parse_distance (s: &str) -> Result<DelayTime, String> {
+	if let Ok(meters) = s.trim().parse::<f64>() {
+		return Ok(DelayTime::Distance(meters));
+	}
+	match s.parse() {
+		Ok(distance @ DelayTime::Distance(_)) => Ok(distance),
+		_ => Err(format!("invalid distance \"{}\", expected e.g. 12.5m or 41ft", s)),
+	}
+}
+
+/// A tap as given on the command line, e.g. `250ms:0.8`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TapSpec {
+	time: DelayTime,
+	gain: f32,
+	/// position between the left (-1.0) and the right (1.0) output
+	pan: Option<f32>,
+}
+
+impl FromStr for TapSpec {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		let mut parts = s.splitn(3, ':');
+		let time = parts.next().unwrap_or("").parse()?;
+		let gain = match parts.next() {
+			Some(gain) => gain
+				.parse()
+				.map_err(|_| format!("invalid tap gain \"{}\"", gain))?,
+			None => 1.0,
+		};
+		let pan = match parts.next() {
+			Some(pan) => match pan.parse::<f32>() {
+				Ok(pan) if (-1.0..=1.0).contains(&pan) => Some(pan),
+				_ => return Err(format!("invalid tap pan \"{}\", expected -1.0 to 1.0", pan)),
+			},
+			None => None,
+		};
+		Ok(TapSpec { time, gain, pan })
+	}
+}
+
+/// A delay unit as given on the command line, e.g. `front:12ms`.
+#[derive(Clone, Debug, PartialEq)]
+struct UnitSpec {
+	name: String,
+	delay: DelayTime,
+}
+
+impl FromStr for UnitSpec {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		match s.split_once(':') {
+			Some((name, delay)) if !name.is_empty() && !name.contains(char::is_whitespace) => Ok(UnitSpec {
+				name: name.to_string(),
+				delay: delay.parse()?,
+			}),
+			_ => Err(format!("invalid unit \"{}\", expected <name>:<delay> with a name without spaces", s)),
+		}
+	}
+}
+
+/// Signal on the monitor outputs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MonitorSource {
+	Dry,
+	Wet,
+	/// dry minus wet, silent where the two line up
+	Difference,
+}
+
+impl MonitorSource {
+	/// Gains of the dry and the wet signal.
+	fn gains (self) -> [f32; 2] {
+		match self {
+			MonitorSource::Dry => [1.0, 0.0],
+			MonitorSource::Wet => [0.0, 1.0],
+			MonitorSource::Difference => [1.0, -1.0],
+		}
+	}
+}
+
+impl FromStr for MonitorSource {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"dry" => Ok(MonitorSource::Dry),
+			"wet" => Ok(MonitorSource::Wet),
+			"difference" | "diff" => Ok(MonitorSource::Difference),
+			_ => Err(format!("expected dry, wet or difference, got \"{}\"", s)),
+		}
+	}
+}
+
+/// Latency reported for an output with `--latency-range`, e.g. `10ms:14ms`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LatencyRange {
+	min: DelayTime,
+	max: DelayTime,
+}
+
+impl FromStr for LatencyRange {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		let (min, max) = s.split_once(':').ok_or_else(|| format!("invalid latency range \"{}\", expected <min>:<max>", s))?;
+		Ok(LatencyRange { min: min.parse()?, max: max.parse()? })
+	}
+}
+
+/// Names of the channels given with `--port-names`, e.g. `mainL,mainR`.
+#[derive(Clone, Debug, PartialEq)]
+struct ChannelNames(Vec<String>);
+
+impl FromStr for ChannelNames {
+	type Err = String;
+
+	fn from_str (s: &str) -> Result<Self, Self::Err> {
+		let names: Vec<String> = s.split(',').map(|name| name.trim().to_string()).collect();
+		if names.len() > 2 {
+			return Err(format!("expected one name per channel, got {}", names.len()));
+		}
+		if let Some(name) = names.iter().find(|name| name.is_empty() || name.contains(':')) {
+			return Err(format!("invalid channel name \"{}\", it may be neither empty nor contain ':'", name));
+		}
+		if names.len() == 2 && names[0] == names[1] {
+			return Err(format!("both channels are named \"{}\"", names[0]));
+		}
+		Ok(ChannelNames(names))
+	}
+}
+
+/// How the ports are named, `<prefix><kind><channel>` with the channels
+/// numbered from 1 unless they are named, like `in1` or `zone1_out_mainL`.
+struct PortNames {
+	prefix: String,
+	channels: Option<ChannelNames>,
+}
+
+impl PortNames {
+	fn channel (&self, kind: &str, channel: usize) -> String {
+		match &self.channels {
+			Some(ChannelNames(names)) => format!("{}{}_{}", self.prefix, kind, names[channel]),
+			None => format!("{}{}{}", self.prefix, kind, channel + 1),
+		}
+	}
+
+	/// A port that isn't per channel, like the sidechain input.
+	fn single (&self, kind: &str) -> String {
+		format!("{}{}", self.prefix, kind)
+	}
+}
+
+/// A change requested through the control interface while the client is running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+	Delay(DelayTime),
+	Feedback(f32),
+	Mix(f32),
+	/// gain in dB
+	InputGain(f32),
+	/// gain in dB
+	OutputGain(f32),
+	/// glide time of delay changes in milliseconds
+	Glide(f64),
+	Freeze(bool),
+	Bypass(bool),
+	/// mirror commands for one channel to the other
+	Link(bool),
+	/// position of the head mode switch in tape echo mode
+	Heads(usize),
+	/// send the input into the delay in throw mode
+	Throw(bool),
+	/// average interval between taps in seconds
+	TapInterval(f64),
+	Monitor(MonitorSource),
+}
+
+/// Parses a line from the control interface, e.g. `delay 120ms`.
+fn parse_command (line: &str) -> Result<Command, String> {
+	let mut words = line.split_whitespace();
+	match (words.next(), words.next(), words.next()) {
+		(Some("delay"), Some(value), None) => Ok(Command::Delay(value.parse()?)),
+		(Some("delay"), _, _) => Err("usage: delay <amount>".to_string()),
+		(Some("feedback"), Some(value), None) => Ok(Command::Feedback(parse_fraction(value)?)),
+		(Some("feedback"), _, _) => Err("usage: feedback <0.0 - 1.0>".to_string()),
+		(Some("mix"), Some(value), None) => Ok(Command::Mix(parse_fraction(value)?)),
+		(Some("mix"), _, _) => Err("usage: mix <0.0 - 1.0>".to_string()),
+		(Some("input-gain"), Some(value), None) => Ok(Command::InputGain(parse_gain(value)?)),
+		(Some("input-gain"), _, _) => Err("usage: input-gain <dB>".to_string()),
+		(Some("output-gain"), Some(value), None) => Ok(Command::OutputGain(parse_gain(value)?)),
+		(Some("output-gain"), _, _) => Err("usage: output-gain <dB>".to_string()),
+		(Some("glide"), Some(value), None) => Ok(Command::Glide(parse_glide(value)?)),
+		(Some("glide"), _, _) => Err("usage: glide <ms>".to_string()),
+		(Some("freeze"), Some(value), None) => Ok(Command::Freeze(parse_toggle(value)?)),
+		(Some("freeze"), _, _) => Err("usage: freeze <on | off>".to_string()),
+		(Some("bypass"), Some(value), None) => Ok(Command::Bypass(parse_toggle(value)?)),
+		(Some("bypass"), _, _) => Err("usage: bypass <on | off>".to_string()),
+		(Some("link"), Some(value), None) => Ok(Command::Link(parse_toggle(value)?)),
+		(Some("link"), _, _) => Err("usage: link <on | off>".to_string()),
+		(Some("heads"), Some(value), None) => Ok(Command::Heads(parse_heads(value)?)),
+		(Some("heads"), _, _) => Err("usage: heads <1 - 7>".to_string()),
+		(Some("throw"), Some(value), None) => Ok(Command::Throw(parse_toggle(value)?)),
+		(Some("throw"), _, _) => Err("usage: throw <on | off>".to_string()),
+		(Some("monitor"), Some(value), None) => Ok(Command::Monitor(value.parse()?)),
+		(Some("monitor"), _, _) => Err("usage: monitor <dry | wet | difference>".to_string()),
+		(Some(other), _, _) => Err(format!("unknown command \"{}\"", other)),
+		(None, _, _) => Err("empty command".to_string()),
+	}
+}
+
+/// A command together with the unit and the channel it is addressed to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Control {
+	/// index of the unit, `None` for all of them
+	unit: Option<usize>,
+	/// index of the line, `None` for both
+	channel: Option<usize>,
+	command: Command,
+}
+
+/// Parses a line from the control interface that may start with the unit
+/// and the channel it is meant for, e.g. `front left feedback 0.3`.
+fn parse_control (line: &str, units: &[String]) -> Result<Control, String> {
+	let (unit, line) = match line.split_once(char::is_whitespace) {
+		Some((name, rest)) => match units.iter().position(|unit| unit == name) {
+			Some(unit) => (Some(unit), rest.trim_start()),
+			None => (None, line),
+		},
+		_ => (None, line),
+	};
+	let (channel, rest) = match line.split_once(char::is_whitespace) {
+		Some(("left", rest)) => (Some(0), rest),
+		Some(("right", rest)) => (Some(1), rest),
+		_ => (None, line),
+	};
+	let command = parse_command(rest)?;
+	match command {
+		Command::Delay(_) | Command::Feedback(_) | Command::Mix(_) | Command::Freeze(_) => {}
+		_ if channel.is_some() => return Err(format!("\"{}\" always applies to both channels", rest.trim())),
+		_ => {}
+	}
+	Ok(Control { unit, channel, command })
+}
+
+/// Collects taps and averages the intervals between the most recent ones.
+struct TapTempo {
+	taps: VecDeque<Instant>,
+}
+
+impl TapTempo {
+	/// number of taps the average is taken over
+	const TAPS: usize = 5;
+	/// a longer pause starts a new series of taps
+	const TIMEOUT: Duration = Duration::from_secs(2);
+
+	fn new () -> Self {
+		TapTempo { taps: VecDeque::with_capacity(Self::TAPS) }
+	}
+
+	/// Records a tap and returns the average interval in seconds once there are at least two.
+	fn tap (&mut self, now: Instant) -> Option<f64> {
+		if let Some(last) = self.taps.back() {
+			if now.duration_since(*last) > Self::TIMEOUT {
+				self.taps.clear();
+			}
+		}
+		if self.taps.len() == Self::TAPS {
+			self.taps.pop_front();
+		}
+		self.taps.push_back(now);
+
+		let first = self.taps.front()?;
+		let intervals = self.taps.len() - 1;
+		if intervals == 0 {
+			return None;
+		}
+		Some(now.duration_since(*first).as_secs_f64() / intervals as f64)
+	}
+}
+
+/// Delay changes at fixed positions of the JACK transport.
+#[derive(Clone, Debug)]
+struct Automation {
+	/// transport position in seconds and the delay from there on, ascending
+	breakpoints: Vec<(f64, DelayTime)>,
+	/// index of the breakpoint applied last
+	current: Option<usize>,
+}
+
+impl Automation {
+	/// Reads breakpoints from a file, see `parse`.
+	fn load (path: &str) -> Result<Self, String> {
+		let text = std::fs::read_to_string(path).map_err(|e| format!("can't read \"{}\": {}", path, e))?;
+		Self::parse(&text)
+	}
+
+	/// Parses one breakpoint per line, e.g. `4.5 1/8d`. Empty lines and lines starting with `#` are skipped.
+	fn parse (text: &str) -> Result<Self, String> {
+		let mut breakpoints: Vec<(f64, DelayTime)> = vec![];
+		for (number, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let error = |e: String| format!("line {}: {}", number + 1, e);
+			let mut words = line.split_whitespace();
+			let breakpoint = match (words.next(), words.next(), words.next()) {
+				(Some(time), Some(delay), None) => match time.parse::<f64>() {
+					Ok(time) if time >= 0.0 => (time, delay.parse().map_err(error)?),
+					_ => return Err(error(format!("invalid time \"{}\", expected seconds", time))),
+				},
+				_ => return Err(error("expected <seconds> <delay>".to_string())),
+			};
+			if breakpoints.last().is_some_and(|&(last, _)| last >= breakpoint.0) {
+				return Err(error("times have to be ascending".to_string()));
+			}
+			breakpoints.push(breakpoint);
+		}
+		Ok(Automation { breakpoints, current: None })
+	}
+
+	/// Returns the delay to switch to once the transport at `seconds` reached another breakpoint.
+	fn update (&mut self, seconds: f64) -> Option<DelayTime> {
+		let index = self.breakpoints.partition_point(|&(time, _)| time <= seconds).checked_sub(1);
+		if index == self.current {
+			return None;
+		}
+		self.current = index;
+		index.map(|index| self.breakpoints[index].1)
+	}
+}
+
+/// Paths of the delay matrix read from the file given with `--matrix`.
+#[derive(Clone, Debug)]
+struct Routing {
+	/// input, output, delay and gain in dB of each path, inputs and outputs counted from 0
+	paths: Vec<(usize, usize, DelayTime, f32)>,
+}
+
+impl Routing {
+	/// Reads paths from a file, see `parse`.
+	fn load (path: &str) -> Result<Self, String> {
+		let text = std::fs::read_to_string(path).map_err(|e| format!("can't read \"{}\": {}", path, e))?;
+		Self::parse(&text)
+	}
+
+	/// Parses one path per line as `<input> <output> <delay> [<gain in dB>]` with inputs and outputs
+	/// counted from 1, e.g. `2 3 12ms -6`. Empty lines and lines starting with `#` are skipped.
+	fn parse (text: &str) -> Result<Self, String> {
+		let mut paths = vec![];
+		for (number, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let error = |e: String| format!("line {}: {}", number + 1, e);
+			let port = |word: &str| match word.parse::<usize>() {
+				Ok(port) if port > 0 => Ok(port - 1),
+				_ => Err(error(format!("invalid port number \"{}\", counted from 1", word))),
+			};
+			let mut words = line.split_whitespace();
+			let path = match (words.next(), words.next(), words.next(), words.next(), words.next()) {
+				(Some(input), Some(output), Some(delay), gain, None) => (
+					port(input)?,
+					port(output)?,
+					delay.parse().map_err(error)?,
+					gain.map_or(Ok(0.0), parse_gain).map_err(error)?,
+				),
+				_ => return Err(error("expected <input> <output> <delay> [<gain in dB>]".to_string())),
+			};
+			paths.push(path);
+		}
+		if paths.is_empty() {
+			return Err("the matrix has no paths".to_string());
+		}
+		Ok(Routing { paths })
+	}
+
+	fn inputs (&self) -> usize {
+		self.paths.iter().map(|&(input, _, _, _)| input + 1).max().unwrap_or(0)
+	}
+
+	fn outputs (&self) -> usize {
+		self.paths.iter().map(|&(_, output, _, _)| output + 1).max().unwrap_or(0)
+	}
+}
+
+/// Ring of slots shared by one sending and one receiving thread.
+/// Neither side locks or allocates, so the process callback can receive from it.
+struct Queue<T> {
+	slots: Box<[UnsafeCell<Option<T>>]>,
+	/// count of values sent so far, only written by the sender
+	sent: AtomicUsize,
+	/// count of values received so far, only written by the receiver
+	received: AtomicUsize,
+}
+
+// a slot is only touched by the sender before `sent` passes it and only by the receiver after
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+/// Sending half of a queue, see `queue`.
+struct QueueSender<T>(Arc<Queue<T>>);
+
+/// Receiving half of a queue, see `queue`.
+struct QueueReceiver<T>(Arc<Queue<T>>);
+
+/// Creates a queue holding up to `capacity` values, which must be a power of two
+/// so the counts stay consistent with the slots when they wrap around.
+fn queue<T> (capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+	assert!(capacity.is_power_of_two());
+	let queue = Arc::new(Queue {
+		slots: (0..capacity).map(|_| UnsafeCell::new(None)).collect(),
+		sent: AtomicUsize::new(0),
+		received: AtomicUsize::new(0),
+	});
+	(QueueSender(queue.clone()), QueueReceiver(queue))
+}
+
+impl<T> QueueSender<T> {
+	/// Hands the value to the receiver, or back if the queue is full.
+	fn try_send (&mut self, value: T) -> Result<(), T> {
+		let queue = &*self.0;
+		let sent = queue.sent.load(Ordering::Relaxed);
+		if sent.wrapping_sub(queue.received.load(Ordering::Acquire)) == queue.slots.len() {
+			return Err(value);
+		}
+		unsafe {
+			*queue.slots[sent % queue.slots.len()].get() = Some(value);
+		}
+		queue.sent.store(sent.wrapping_add(1), Ordering::Release);
+		Ok(())
+	}
+
+	/// Whether the receiving half was dropped.
+	fn is_disconnected (&self) -> bool {
+		Arc::strong_count(&self.0) == 1
+	}
+}
+
+impl<T> QueueReceiver<T> {
+	/// Takes the oldest value, if any.
+	fn try_recv (&mut self) -> Option<T> {
+		let queue = &*self.0;
+		let received = queue.received.load(Ordering::Relaxed);
+		if received == queue.sent.load(Ordering::Acquire) {
+			return None;
+		}
+		let value = unsafe { (*queue.slots[received % queue.slots.len()].get()).take() };
+		queue.received.store(received.wrapping_add(1), Ordering::Release);
+		value
+	}
+
+	/// Count of values waiting to be received.
+	fn len (&self) -> usize {
+		let queue = &*self.0;
+		queue.sent.load(Ordering::Acquire).wrapping_sub(queue.received.load(Ordering::Relaxed))
+	}
+}
+
+/// Meters published by the process callback for the `status` command.
+#[derive(Default)]
+struct Status {
+	/// most gain reduction of the output limiter in dB since the last report, as bits of an f32
+	limiter_reduction: AtomicU32,
+	/// process callbacks so far, for the watchdog
+	periods: AtomicUsize,
+	/// set by the watchdog before it shuts the client down
+	stalled: AtomicBool,
+	/// whether a server runs the client, the watchdog only keeps track while it does
+	active: AtomicBool,
+	/// set while the server renders faster than realtime, which pauses the meters and the watchdog
+	freewheeling: AtomicBool,
+	/// levels of both outputs of each unit since the last report, kept up to date by the metering thread
+	meters: Mutex<Vec<[Meter; 2]>>,
+	/// set when a change of the sample rate cut delays to the buffers, reported by the metering thread
+	cut_delays: AtomicBool,
+	/// set while a change of the connections is waiting for the main thread, so a whole
+	/// patch connected at once gives a single `Event::ConnectionsChanged`
+	connections_changed: AtomicBool,
+}
+
+impl Status {
+	fn report_limiter (&self, reduction_db: f32) {
+		// the bits of positive floats sort like the floats themselves
+		self.limiter_reduction.fetch_max(reduction_db.to_bits(), Ordering::Relaxed);
+	}
+
+	fn take_limiter (&self) -> f32 {
+		f32::from_bits(self.limiter_reduction.swap(0, Ordering::Relaxed))
+	}
+
+	fn take_meters (&self) -> Vec<[Meter; 2]> {
+		self.meters.lock().unwrap().iter_mut().map(std::mem::take).collect()
+	}
+}
+
+/// Level of one output over a stretch of time.
+#[derive(Clone, Copy, Default)]
+struct Meter {
+	peak: f32,
+	sum_of_squares: f64,
+	frames: usize,
+	/// frames at or above full scale
+	clipped: usize,
+}
+
+impl Meter {
+	fn add (&mut self, samples: &[f32]) {
+		for &sample in samples {
+			self.peak = self.peak.max(sample.abs());
+			self.sum_of_squares += sample as f64 * sample as f64;
+			if sample.abs() >= 1.0 {
+				self.clipped += 1;
+			}
+		}
+		self.frames += samples.len();
+	}
+
+	fn rms (&self) -> f32 {
+		(self.sum_of_squares / self.frames.max(1) as f64).sqrt() as f32
+	}
+}
+
+/// Frames of both outputs at a time sent to the metering thread.
+const METER_BLOCK_FRAMES: usize = 1024;
+
+/// Blocks to go around, fewer than a queue holds so sending them never fails.
+const METER_BLOCKS: usize = 32;
+
+/// Copies of the outputs of a unit on their way to the metering thread.
+struct MeterBlock {
+	unit: usize,
+	outputs: [Vec<f32>; 2],
+}
+
+/// The process callback's end of the metering pipeline, which gets blocks to
+/// fill from the metering thread and sends them back filled. The callback only copies,
+/// so metering never adds to its load, what doesn't fit while the thread lags is left out.
+struct MeterSender {
+	filled: QueueSender<MeterBlock>,
+	empty: QueueReceiver<MeterBlock>,
+}
+
+impl MeterSender {
+	fn send (&mut self, unit: usize, outputs: [&[f32]; 2]) {
+		let frames = outputs[0].len().min(outputs[1].len());
+		for start in (0..frames).step_by(METER_BLOCK_FRAMES) {
+			let mut block = match self.empty.try_recv() {
+				Some(block) => block,
+				None => return,
+			};
+			let end = (start + METER_BLOCK_FRAMES).min(frames);
+			block.unit = unit;
+			for (copy, output) in block.outputs.iter_mut().zip(&outputs) {
+				// within the capacity, so this doesn't allocate
+				copy.clear();
+				copy.extend_from_slice(&output[start..end]);
+			}
+			if let Err(block) = self.filled.try_send(block) {
+				// can't happen with fewer blocks than slots, but leaking beats freeing on this thread
+				std::mem::forget(block);
+			}
+		}
+	}
+}
+
+/// Starts the metering thread, which sums up the blocks from the returned sender into `status`.
+fn start_metering (status: Arc<Status>) -> MeterSender {
+	let (filled, mut filled_rx) = queue::<MeterBlock>(METER_BLOCKS * 2);
+	let (mut empty_tx, empty) = queue(METER_BLOCKS * 2);
+	for _ in 0..METER_BLOCKS {
+		let block = MeterBlock { unit: 0, outputs: [Vec::with_capacity(METER_BLOCK_FRAMES), Vec::with_capacity(METER_BLOCK_FRAMES)] };
+		// the queue holds all of them
+		let _ = empty_tx.try_send(block);
+	}
+	std::thread::spawn(move || {
+		while !empty_tx.is_disconnected() {
+			while let Some(block) = filled_rx.try_recv() {
+				let mut meters = status.meters.lock().unwrap();
+				if meters.len() <= block.unit {
+					meters.resize(block.unit + 1, Default::default());
+				}
+				for (meter, output) in meters[block.unit].iter_mut().zip(&block.outputs) {
+					meter.add(output);
+				}
+				drop(meters);
+				let _ = empty_tx.try_send(block);
+			}
+			// the process callback can't print without risking an xrun
+			if status.cut_delays.swap(false, Ordering::Relaxed) {
+				eprintln!("warning: delays were cut to the buffer at the new sample rate, give a longer --max-delay to make room");
+			}
+			std::thread::sleep(Duration::from_millis(5));
+		}
+	});
+	MeterSender { filled, empty }
+}
+
+/// Shuts the client down once the process callback hasn't run for `timeout`,
+/// e.g. because the server hung or dropped the client without notice.
+fn watchdog (status: Arc<Status>, timeout: Duration) {
+	let mut periods = status.periods.load(Ordering::Relaxed);
+	let mut last_period = Instant::now();
+	loop {
+		std::thread::sleep(timeout / 10);
+		let now = status.periods.load(Ordering::Relaxed);
+		let waiting = !status.active.load(Ordering::Relaxed) || status.freewheeling.load(Ordering::Relaxed);
+		if now != periods || waiting {
+			periods = now;
+			last_period = Instant::now();
+		} else if last_period.elapsed() >= timeout {
+			status.stalled.store(true, Ordering::Relaxed);
+			request_shutdown();
+			return;
+		}
+	}
+}
+
+/// Signals on which the client deactivates and exits.
+const SHUTDOWN_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// Blocks the shutdown signals for this thread and all threads started from it later, so they are
+/// only ever taken by `wait_for_shutdown` rather than interrupting whatever thread they hit.
+fn block_shutdown_signals () -> libc::sigset_t {
+	unsafe {
+		let mut signals = std::mem::zeroed();
+		libc::sigemptyset(&mut signals);
+		for &signal in &SHUTDOWN_SIGNALS {
+			libc::sigaddset(&mut signals, signal);
+		}
+		libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+		signals
+	}
+}
+
+/// Sleeps until one of the blocked shutdown `signals` arrives and returns it.
+fn wait_for_shutdown (signals: &libc::sigset_t) -> libc::c_int {
+	let mut signal = 0;
+	unsafe {
+		libc::sigwait(signals, &mut signal);
+	}
+	signal
+}
+
+/// Wakes up `wait_for_shutdown` from any thread, safe to call in callbacks.
+fn request_shutdown () {
+	unsafe {
+		libc::kill(libc::getpid(), libc::SIGTERM);
+	}
+}
+
+/// Reads commands from stdin line by line and forwards them to the process callback.
+/// An empty line or `tap` counts as a tap for the tap tempo, `status` prints the meters
+/// and `quit` shuts the client down.
+/// `units` holds the names of the units and `outputs` the output ports of each.
+fn control_loop (
+	commands: Arc<Mutex<QueueSender<Control>>>,
+	status: Arc<Status>,
+	limiting: bool,
+	units: Vec<String>,
+	outputs: Vec<Vec<String>>,
+	events: std::sync::mpsc::Sender<Event>,
+) {
+	let stdin = std::io::stdin();
+	let mut tap_tempo = TapTempo::new();
+	for line in stdin.lock().lines() {
+		let line = match line {
+			Ok(line) => line,
+			Err(_) => break,
+		};
+		let line = line.trim();
+		if line == "quit" {
+			request_shutdown();
+			return;
+		}
+		if line == "save" {
+			let _ = events.send(Event::SaveConnections);
+			continue;
+		}
+		if line == "status" {
+			if limiting {
+				println!("limiter: {:.1} dB gain reduction", status.take_limiter());
+			} else {
+				println!("limiter: off");
+			}
+			if status.freewheeling.load(Ordering::Relaxed) {
+				println!("meters: paused while freewheeling");
+			}
+			for (outputs, meters) in outputs.iter().zip(&status.take_meters()) {
+				for (name, meter) in outputs.iter().zip(meters) {
+					println!(
+						"{}: peak {:.1} dBFS, rms {:.1} dBFS, {} frames clipped",
+						name, 20.0 * meter.peak.log10(), 20.0 * meter.rms().log10(), meter.clipped
+					);
+				}
+			}
+			continue;
+		}
+		let tapped = line.is_empty() || line == "tap";
+		let command = if tapped {
+			match tap_tempo.tap(Instant::now()) {
+				Some(interval) => Ok(Control { unit: None, channel: None, command: Command::TapInterval(interval) }),
+				None => continue,
+			}
+		} else {
+			parse_control(line, &units)
+		};
+		match command {
+			Ok(mut control) => {
+				// waits for the process callback to make room instead of losing the command,
+				// and while the server is gone for the client that replaces the old one
+				loop {
+					let mut sender = commands.lock().unwrap();
+					// a queue nobody receives from would take the command and drop it
+					let disconnected = sender.is_disconnected();
+					if !disconnected {
+						match sender.try_send(control) {
+							Ok(()) => break,
+							Err(rejected) => control = rejected,
+						}
+					}
+					// unlocked while waiting, so the sender can be replaced for a new client
+					drop(sender);
+					std::thread::sleep(if disconnected { RECONNECT_INTERVAL } else { Duration::from_millis(1) });
+				}
+				if !tapped {
+					let _ = events.send(Event::Command(line.to_string()));
+				}
+			}
+			Err(e) => eprintln!("{}", e),
+		}
+	}
+}
+
+/// Checks that the process callback never allocates, enabled with the `rt-check` feature.
+/// Locks and I/O that don't allocate go unnoticed.
+#[cfg(feature = "rt-check")]
+mod rt_check {
+	use std::alloc::{GlobalAlloc, Layout, System};
+	use std::cell::Cell;
+
+	thread_local! {
+		static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+	}
+
+	/// Marks the current thread as running the process callback until dropped.
+	pub struct Guard;
+
+	impl Guard {
+		pub fn enter () -> Self {
+			IN_CALLBACK.with(|in_callback| in_callback.set(true));
+			Guard
+		}
+	}
+
+	impl Drop for Guard {
+		fn drop (&mut self) {
+			IN_CALLBACK.with(|in_callback| in_callback.set(false));
+		}
+	}
+
+	fn check () {
+		if IN_CALLBACK.try_with(Cell::get).unwrap_or(false) {
+			// printing the usual way would allocate again
+			let message = b"rt-check: the process callback allocated\n";
+			unsafe {
+				libc::write(libc::STDERR_FILENO, message.as_ptr() as *const libc::c_void, message.len());
+			}
+			std::process::abort();
+		}
+	}
+
+	struct CheckedAllocator;
+
+	unsafe impl GlobalAlloc for CheckedAllocator {
+		unsafe fn alloc (&self, layout: Layout) -> *mut u8 {
+			check();
+			System.alloc(layout)
+		}
+
+		unsafe fn dealloc (&self, ptr: *mut u8, layout: Layout) {
+			check();
+			System.dealloc(ptr, layout)
+		}
+
+		unsafe fn realloc (&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+			check();
+			System.realloc(ptr, layout, new_size)
+		}
+	}
+
+	#[global_allocator]
+	static ALLOCATOR: CheckedAllocator = CheckedAllocator;
+}
+
+/// Copies `input` into `output`, silencing whatever `input` is too short for.
+fn copy_or_silence (input: &[f32], output: &mut [f32]) {
+	let frames = input.len().min(output.len());
+	output[..frames].copy_from_slice(&input[..frames]);
+	output[frames..].fill(0.0);
+}
+
+/// Output ports that carry the dry, the wet or the difference signal of the first unit.
+struct Monitor {
+	ports: [jack::Port<jack::AudioOut>; 2],
+	source: MonitorSource,
+	/// gains of the dry and the wet signal, following those of the source over the bypass fade
+	gains: [f32; 2],
+}
+
+impl Monitor {
+	fn new (ports: [jack::Port<jack::AudioOut>; 2], source: MonitorSource) -> Self {
+		Monitor { ports, source, gains: source.gains() }
+	}
+
+	fn write (&mut self, ps: &jack::ProcessScope, dry: [&[f32]; 2], wet: [&[f32]; 2], fade_frames: usize) {
+		let target = self.source.gains();
+		let step = 1.0 / fade_frames.max(1) as f32;
+		let start = self.gains;
+		for ((port, dry), wet) in self.ports.iter_mut().zip(dry).zip(wet) {
+			let mut gains = start;
+			for ((out, &dry), &wet) in port.as_mut_slice(ps).iter_mut().zip(dry).zip(wet) {
+				for (gain, &target) in gains.iter_mut().zip(&target) {
+					*gain = if (target - *gain).abs() <= step { target } else { *gain + step * (target - *gain).signum() };
+				}
+				*out = gains[0] * dry + gains[1] * wet;
+			}
+			self.gains = gains;
+		}
+	}
+}
+
+/// Send and return ports of the external feedback insert.
+struct InsertPorts {
+	sends: [jack::Port<jack::AudioOut>; 2],
+	returns: [jack::Port<jack::AudioIn>; 2],
+}
+
+/// Lets the threads of a `WorkerPool` reach what the process callback holds for one period.
+/// Whoever hands it out makes sure no two threads touch the same part mutably.
+struct Shared<T>(T);
+
+unsafe impl<T> Sync for Shared<T> {}
+
+/// The job of the current period, a task for each index below the count.
+type Job<'a> = &'a (dyn Fn(usize) + Sync);
+
+/// What the process callback and the threads of a `WorkerPool` hand each other.
+#[derive(Default)]
+struct PoolState {
+	/// number of the period in the upper and the tasks left to take in the lower 32 bits
+	tasks: AtomicU64,
+	/// tasks of the period that are done
+	done: AtomicUsize,
+	/// points to the `Job` on the stack of `WorkerPool::run`, valid while tasks are left
+	job: AtomicPtr<()>,
+	/// set when a task panicked, for the process callback to fail in turn
+	panicked: AtomicBool,
+	stop: AtomicBool,
+}
+
+impl PoolState {
+	/// Takes tasks of `period` until none are left.
+	fn work (&self, period: u64) {
+		loop {
+			let tasks = self.tasks.load(Ordering::Acquire);
+			if tasks >> 32 != period || tasks as u32 == 0 {
+				return;
+			}
+			if self.tasks.compare_exchange_weak(tasks, tasks - 1, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+				continue;
+			}
+			// the period can't end before this task is done, so the job is still there
+			let job = unsafe { *(self.job.load(Ordering::Relaxed) as *const Job) };
+			let index = (tasks as u32 - 1) as usize;
+			// unwinding past the others would leave them with a job that is gone
+			if std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job(index))).is_err() {
+				self.panicked.store(true, Ordering::Relaxed);
+			}
+			self.done.fetch_add(1, Ordering::Release);
+		}
+	}
+}
+
+/// Threads started once with `--workers` that share the work of each period with the process callback.
+/// Handing it out and waiting for it neither allocates nor takes a lock, the threads sleep in between.
+struct WorkerPool {
+	state: Arc<PoolState>,
+	threads: Vec<std::thread::JoinHandle<()>>,
+	/// whether the threads run at the priority of the process thread yet
+	promoted: bool,
+}
+
+impl WorkerPool {
+	fn new (workers: usize) -> Self {
+		let state = Arc::new(PoolState::default());
+		let threads = (0..workers).map(|_| {
+			let state = state.clone();
+			std::thread::spawn(move || {
+				let mut period = 0;
+				while !state.stop.load(Ordering::Acquire) {
+					let current = state.tasks.load(Ordering::Acquire) >> 32;
+					if current == period {
+						std::thread::park();
+						continue;
+					}
+					period = current;
+					state.work(period);
+				}
+			})
+		}).collect();
+		WorkerPool { state, threads, promoted: false }
+	}
+
+	/// Runs `job` for every index below `tasks` on the threads and the calling one,
+	/// returning once all are done. Panics if one of them did.
+	fn run (&mut self, tasks: usize, job: Job) {
+		if !self.promoted {
+			self.promote();
+		}
+		let state = &*self.state;
+		state.done.store(0, Ordering::Relaxed);
+		state.job.store(&job as *const Job as *mut (), Ordering::Relaxed);
+		let period = ((state.tasks.load(Ordering::Relaxed) >> 32) + 1) & u64::from(u32::MAX);
+		state.tasks.store(period << 32 | tasks as u64, Ordering::Release);
+		for thread in &self.threads {
+			thread.thread().unpark();
+		}
+		state.work(period);
+		// the rest is about to finish, sleeping would take longer than that
+		while state.done.load(Ordering::Acquire) < tasks {
+			std::hint::spin_loop();
+		}
+		if state.panicked.swap(false, Ordering::Relaxed) {
+			panic!("a worker thread failed");
+		}
+	}
+
+	/// Gives the threads the scheduling of the calling process thread, so they aren't
+	/// preempted by what JACK itself runs ahead of.
+	fn promote (&mut self) {
+		use std::os::unix::thread::JoinHandleExt;
+		self.promoted = true;
+		unsafe {
+			let (mut policy, mut param) = (0, std::mem::zeroed());
+			if libc::pthread_getschedparam(libc::pthread_self(), &mut policy, &mut param) != 0 {
+				return;
+			}
+			for thread in &self.threads {
+				// without the privileges they stay as they are, which only costs time
+				libc::pthread_setschedparam(thread.as_pthread_t(), policy, &param);
+			}
+		}
+	}
+}
+
+impl Drop for WorkerPool {
+	fn drop (&mut self) {
+		self.state.stop.store(true, Ordering::Release);
+		for thread in self.threads.drain(..) {
+			thread.thread().unpark();
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Events and bytes of MIDI the delay holds at most, events past that are dropped.
+const MIDI_ROOM: (usize, usize) = (4096, 65536);
+
+/// MIDI ports registered with `--midi`.
+struct MidiPorts {
+	input: jack::Port<jack::MidiIn>,
+	output: jack::Port<jack::MidiOut>,
+	delay: MidiDelay,
+	/// `None` to follow the first tap of the first channel
+	time: Option<DelayTime>,
+	/// frames the audio is delayed by on top of its taps, to make up for negative delays
+	compensation: f64,
+	/// note held to throw the input in with `--throw-note`
+	throw_note: Option<u8>,
+}
+
+impl MidiPorts {
+	/// Whether the throw note was last pressed or released this period, `None` if neither.
+	fn thrown (&self, ps: &jack::ProcessScope) -> Option<bool> {
+		let note = self.throw_note?;
+		self.input.iter(ps).filter_map(|event| note_state(event.bytes, note)).last()
+	}
+}
+
+/// `Some(true)` if `bytes` are a note on of `note` on any channel, `Some(false)` for a note off,
+/// which may also be a note on with a velocity of 0.
+fn note_state (bytes: &[u8], note: u8) -> Option<bool> {
+	match *bytes {
+		[status, key, velocity] if key == note && status & 0xf0 == 0x90 => Some(velocity > 0),
+		[status, key, _] if key == note && status & 0xf0 == 0x80 => Some(false),
+		_ => None,
+	}
+}
+
+/// A delay with its own ports, more than one run side by side with `--unit`.
+struct Unit {
+	in_1: jack::Port<jack::AudioIn>,
+	/// `None` in mono mode, like `out_2`
+	in_2: Option<jack::Port<jack::AudioIn>>,
+	out_1: jack::Port<jack::AudioOut>,
+	out_2: Option<jack::Port<jack::AudioOut>>,
+	delay: StereoDelay,
+	/// delay of every tap of both channels as requested, kept to follow tempo changes
+	tap_times: [Vec<DelayTime>; 2],
+	/// bypassed with the `bypass` command
+	bypassed: bool,
+	/// discarded output of the idle second line in mono mode, period sized
+	discarded: Vec<f32>,
+}
+
+impl Unit {
+	/// Runs one period of the input ports through the delay into the output ports,
+	/// `silence` stands in for the second input in mono mode.
+	fn process (&mut self, ps: &jack::ProcessScope, silence: &[f32], wet_gains: Option<&[f32]>, cv: Option<&[f32]>, insert: Option<Insert>) {
+		let in_1 = self.in_1.as_slice(ps);
+		let in_2 = match &self.in_2 {
+			Some(port) => port.as_slice(ps),
+			None => silence,
+		};
+		let out_1 = self.out_1.as_mut_slice(ps);
+		let out_2 = match &mut self.out_2 {
+			Some(port) => port.as_mut_slice(ps),
+			None => &mut self.discarded,
+		};
+		self.delay.process([in_1, in_2], [out_1, out_2], wet_gains, cv, insert);
+	}
+}
+
+/// The process handler, delays the input ports of each unit into its output ports.
+struct DelayProcessor {
+	units: Vec<Unit>,
+	/// silent input of the idle second line in mono mode, period sized
+	silence: Vec<f32>,
+	/// share the units with `--workers`
+	workers: Option<WorkerPool>,
+	/// passthrough of the unprocessed inputs
+	dry_outputs: Option<[jack::Port<jack::AudioOut>; 2]>,
+	monitor: Option<Monitor>,
+	sidechain: Option<(jack::Port<jack::AudioIn>, Ducker)>,
+	cv: Option<jack::Port<jack::AudioIn>>,
+	/// frames of the first unit for the client on the `--bridge` server
+	bridge: Option<QueueSender<[f32; 2]>>,
+	feedback_insert: Option<InsertPorts>,
+	midi: Option<MidiPorts>,
+	/// per frame gain of the delayed signal computed from the sidechain
+	duck_gains: Vec<f32>,
+	meters: MeterSender,
+	timebase: Timebase,
+	/// round tapped delays to note values
+	tap_round: bool,
+	/// milliseconds of the bypass fade, and the frames of it at the current sample rate
+	bypass_fade: f64,
+	bypass_fade_frames: usize,
+	/// milliseconds for gains, mix and feedback to follow changes
+	smoothing: f64,
+	/// bypass the delay while the transport is stopped and clear it once faded out
+	transport_gate: bool,
+	/// the transport as last seen
+	rolling: bool,
+	/// frames left until the lines get cleared after the transport stopped
+	flush_in: Option<usize>,
+	/// apply commands for a single channel to both
+	linked: bool,
+	automation: Option<Automation>,
+	/// the first taps are the playback heads of a tape echo
+	tape_heads: bool,
+	/// the input only enters the delay while thrown
+	throw_mode: bool,
+	commands: QueueReceiver<Control>,
+	status: Arc<Status>,
+	/// sample rate of the server as last announced to the notification handler
+	sample_rate: Arc<AtomicUsize>,
+	/// a panic left the state undefined, so the outputs stay silent
+	failed: bool,
+}
+
+impl DelayProcessor {
+	/// Indices of the lines a command for `channel` applies to.
+	fn channels (&self, channel: Option<usize>) -> std::ops::Range<usize> {
+		match channel {
+			Some(channel) if !self.linked => channel..channel + 1,
+			_ => 0..2,
+		}
+	}
+
+	/// Applies `control` to the unit it is addressed to, or to all of them.
+	fn handle_control (&mut self, control: Control) {
+		let units = match control.unit {
+			Some(unit) => unit..unit + 1,
+			None => 0..self.units.len(),
+		};
+		for unit in units {
+			self.handle_command(unit, control.channel, control.command);
+		}
+	}
+
+	fn handle_command (&mut self, unit: usize, channel: Option<usize>, command: Command) {
+		let channels = self.channels(channel);
+		match command {
+			Command::Delay(delay) => {
+				// the heads of a tape echo move together
+				let heads = if self.tape_heads { HEADS } else { 1 };
+				for channel in channels {
+					for head in 0..heads {
+						let delay = delay.scaled((head + 1) as f64);
+						if let Some(time) = self.units[unit].tap_times[channel].get_mut(head) {
+							*time = delay;
+						}
+						self.units[unit].delay.lines_mut()[channel].set_tap_delay(head, delay.to_frames(&self.timebase));
+					}
+				}
+			}
+			Command::Feedback(feedback) => {
+				for line in &mut self.units[unit].delay.lines_mut()[channels] {
+					line.set_feedback(feedback);
+				}
+			}
+			Command::Mix(mix) => {
+				for line in &mut self.units[unit].delay.lines_mut()[channels] {
+					line.set_mix(mix);
+				}
+			}
+			Command::InputGain(db) => {
+				self.units[unit].delay.set_input_gain(db_to_gain(db));
+			}
+			Command::OutputGain(db) => {
+				self.units[unit].delay.set_output_gain(db_to_gain(db));
+			}
+			Command::Glide(ms) => {
+				let frames = millis_to_frames(ms, self.timebase.sample_rate).round() as usize;
+				for line in self.units[unit].delay.lines_mut() {
+					line.set_glide(frames);
+				}
+			}
+			Command::Freeze(frozen) => {
+				for line in &mut self.units[unit].delay.lines_mut()[channels] {
+					line.set_frozen(frozen);
+				}
+			}
+			Command::Bypass(bypassed) => {
+				self.units[unit].bypassed = bypassed;
+				self.update_bypass();
+			}
+			Command::Link(linked) => {
+				self.linked = linked;
+			}
+			Command::Heads(mode) => {
+				if self.tape_heads {
+					for line in self.units[unit].delay.lines_mut() {
+						for (head, &gain) in head_gains(mode).iter().enumerate() {
+							line.set_tap_gain(head, gain);
+						}
+					}
+				}
+			}
+			Command::Throw(thrown) => {
+				if self.throw_mode {
+					self.units[unit].delay.set_send(if thrown { 1.0 } else { 0.0 });
+				}
+			}
+			Command::TapInterval(seconds) => {
+				let delay = if self.tap_round {
+					DelayTime::nearest_note(seconds, self.timebase.tempo)
+				} else {
+					DelayTime::Seconds(seconds)
+				};
+				self.handle_command(unit, channel, Command::Delay(delay));
+			}
+			Command::Monitor(source) => {
+				if let Some(monitor) = &mut self.monitor {
+					monitor.source = source;
+				}
+			}
+		}
+	}
+
+	/// Applies pending changes and delays the inputs of one period into the outputs.
+	fn run_period (&mut self, client: &jack::Client, ps: &jack::ProcessScope) {
+		while let Some(control) = self.commands.try_recv() {
+			self.handle_control(control);
+		}
+		self.set_sample_rate(self.sample_rate.load(Ordering::Relaxed));
+
+		if let Ok(state) = client.transport().query() {
+			if let Some(bbt) = state.pos.bbt() {
+				self.set_tempo(Tempo { bpm: bbt.bpm, beat_type: bbt.sig_denom as f64 });
+			}
+			// follows relocations as well, so a performance can be started from anywhere
+			let seconds = state.pos.frame() as f64 / self.timebase.sample_rate as f64;
+			if let Some(delay) = self.automation.as_mut().and_then(|automation| automation.update(seconds)) {
+				self.handle_control(Control { unit: None, channel: None, command: Command::Delay(delay) });
+			}
+			let rolling = state.state == jack::TransportState::Rolling;
+			if self.transport_gate && rolling != self.rolling {
+				self.rolling = rolling;
+				self.update_bypass();
+				self.flush_in = if rolling { None } else { Some(self.bypass_fade_frames) };
+			}
+		}
+
+		if let Some(thrown) = self.midi.as_ref().and_then(|midi| midi.thrown(ps)) {
+			self.handle_control(Control { unit: None, channel: None, command: Command::Throw(thrown) });
+		}
+
+		let wet_gains = match &mut self.sidechain {
+			Some((port, ducker)) => {
+				// the gains are allocated for the period size, frames past that aren't ducked
+				let sidechain_p = port.as_slice(ps);
+				let sidechain_p = &sidechain_p[..sidechain_p.len().min(self.duck_gains.len())];
+				let gains = &mut self.duck_gains[..sidechain_p.len()];
+				ducker.process(sidechain_p, gains);
+				Some(&*gains)
+			}
+			None => None,
+		};
+
+		let cv = self.cv.as_ref().map(|port| port.as_slice(ps));
+		let silence = &self.silence;
+		match &mut self.workers {
+			// there are neither dry outputs nor an insert with workers
+			Some(workers) => {
+				let (units, ps) = (Shared(self.units.as_mut_ptr()), Shared(ps));
+				workers.run(self.units.len(), &|index| {
+					// every index is run once, so each unit is only ever on one thread
+					let unit = unsafe { &mut *units.0.add(index) };
+					unit.process(ps.0, silence, wet_gains, cv, None);
+				});
+			}
+			None => for (index, unit) in self.units.iter_mut().enumerate() {
+				// dry outputs and the insert only exist without further units
+				if let (0, Some([dry_1, dry_2])) = (index, &mut self.dry_outputs) {
+					copy_or_silence(unit.in_1.as_slice(ps), dry_1.as_mut_slice(ps));
+					copy_or_silence(unit.in_2.as_ref().map_or(&silence[..], |port| port.as_slice(ps)), dry_2.as_mut_slice(ps));
+				}
+				let insert = self.feedback_insert.as_mut().filter(|_| index == 0).map(|InsertPorts { sends: [send_1, send_2], returns }| Insert {
+					sends: [send_1.as_mut_slice(ps), send_2.as_mut_slice(ps)],
+					returns: [&returns[0], &returns[1]].map(|port| match port.connected_count() {
+						Ok(count) if count > 0 => Some(port.as_slice(ps)),
+						_ => None,
+					}),
+				});
+				unit.process(ps, silence, wet_gains, cv, insert);
+			},
+		}
+
+		for (index, unit) in self.units.iter_mut().enumerate() {
+			let in_1_p = unit.in_1.as_slice(ps);
+			let in_2_p = match &unit.in_2 {
+				Some(port) => port.as_slice(ps),
+				None => &self.silence,
+			};
+			let out_1_p = unit.out_1.as_mut_slice(ps);
+			let out_2_p = match &mut unit.out_2 {
+				Some(port) => port.as_mut_slice(ps),
+				None => &mut unit.discarded,
+			};
+			if let Some(gain) = unit.delay.take_limiter_gain() {
+				self.status.report_limiter(20.0 * gain.recip().log10());
+			}
+			// a render goes through far more frames than the metering thread keeps up with
+			if !self.status.freewheeling.load(Ordering::Relaxed) {
+				self.meters.send(index, [out_1_p, out_2_p]);
+			}
+			// the monitor outputs follow the first unit like the bridge
+			if let (0, Some(monitor)) = (index, &mut self.monitor) {
+				monitor.write(ps, [in_1_p, in_2_p], [out_1_p, out_2_p], self.bypass_fade_frames);
+			}
+			// frames the bridge has no room for are lost, it skips ahead in turn when it gets too many
+			if let (0, Some(bridge)) = (index, &mut self.bridge) {
+				for (&left, &right) in out_1_p.iter().zip(out_2_p.iter()) {
+					let _ = bridge.try_send([left, right]);
+				}
+			}
+		}
+
+		if let Some(midi) = &mut self.midi {
+			let (units, timebase) = (&self.units, &self.timebase);
+			let time = midi.time.or_else(|| units[0].tap_times[0].first().copied());
+			let frames = time.map_or(0.0, |time| time.to_frames(timebase)) + midi.compensation;
+			midi.delay.set_delay(frames.max(0.0).round() as usize);
+			for event in midi.input.iter(ps) {
+				if midi.throw_note.is_none_or(|note| note_state(event.bytes, note).is_none()) {
+					midi.delay.push(event.time as usize, event.bytes);
+				}
+			}
+			let mut writer = midi.output.writer(ps);
+			midi.delay.release(ps.n_frames() as usize, |time, bytes| {
+				// events past the room of the port are dropped like those past the room of the delay
+				let _ = writer.write(&jack::RawMidi { time: time as jack::Frames, bytes });
+			});
+		}
+
+		if let Some(frames) = self.flush_in {
+			// the tails are faded out by now, so nothing of them is left when the transport starts again
+			match frames.checked_sub(ps.n_frames() as usize) {
+				Some(frames) if frames > 0 => self.flush_in = Some(frames),
+				_ => {
+					for line in self.units.iter_mut().flat_map(|unit| unit.delay.lines_mut()) {
+						line.clear();
+					}
+					self.flush_in = None;
+				}
+			}
+		}
+	}
+
+	/// Bypasses the delay on the `bypass` command and while the transport gate is closed.
+	fn update_bypass (&mut self) {
+		let gated = self.transport_gate && !self.rolling;
+		for unit in &mut self.units {
+			unit.delay.set_bypassed(unit.bypassed || gated, self.bypass_fade_frames);
+		}
+	}
+
+	/// Writes silence to every output port.
+	fn silence (&mut self, ps: &jack::ProcessScope) {
+		for unit in &mut self.units {
+			unit.out_1.as_mut_slice(ps).fill(0.0);
+			if let Some(port) = &mut unit.out_2 {
+				port.as_mut_slice(ps).fill(0.0);
+			}
+		}
+		for port in self.dry_outputs.iter_mut().flatten().chain(self.monitor.iter_mut().flat_map(|monitor| &mut monitor.ports)) {
+			port.as_mut_slice(ps).fill(0.0);
+		}
+		for port in self.feedback_insert.iter_mut().flat_map(|insert| &mut insert.sends) {
+			port.as_mut_slice(ps).fill(0.0);
+		}
+		// getting the writer clears the events of the last period
+		if let Some(midi) = &mut self.midi {
+			midi.output.writer(ps);
+		}
+	}
+
+	/// Recomputes tempo synced taps when the tempo changed.
+	fn set_tempo (&mut self, tempo: Tempo) {
+		if tempo == self.timebase.tempo || tempo.bpm <= 0.0 {
+			return;
+		}
+		self.timebase.tempo = tempo;
+		self.update_taps(DelayTime::is_tempo_synced);
+	}
+
+	/// Carries filters, modulation, fades and the taps in time units over when the sample
+	/// rate changed, plain frame counts and the contents of the lines stay as they are.
+	fn set_sample_rate (&mut self, sample_rate: usize) {
+		let previous = self.timebase.sample_rate;
+		if sample_rate == previous || sample_rate == 0 {
+			return;
+		}
+		self.timebase.sample_rate = sample_rate;
+		self.bypass_fade_frames = millis_to_frames(self.bypass_fade, sample_rate).round() as usize;
+		let smoothing = smoothing_coefficient(self.smoothing, sample_rate);
+		let mut fit = true;
+		for unit in &mut self.units {
+			fit &= unit.delay.set_sample_rate(previous, sample_rate);
+			unit.delay.set_smoothing(smoothing);
+		}
+		if let Some((_, ducker)) = &mut self.sidechain {
+			ducker.set_sample_rate(sample_rate);
+		}
+		fit &= self.update_taps(|time| !matches!(time, DelayTime::Frames(_)));
+		if !fit {
+			self.status.cut_delays.store(true, Ordering::Relaxed);
+		}
+	}
+
+	/// Recomputes the delay of each tap whose requested time matches `filter`,
+	/// returns false if one of them had to be cut to the buffer.
+	fn update_taps (&mut self, filter: fn(DelayTime) -> bool) -> bool {
+		let mut fit = true;
+		for unit in &mut self.units {
+			for (line, times) in unit.delay.lines_mut().iter_mut().zip(&unit.tap_times) {
+				for (index, &time) in times.iter().enumerate() {
+					if filter(time) {
+						let delay = time.to_frames(&self.timebase);
+						fit &= delay <= line.capacity();
+						line.set_tap_delay(index, delay);
+					}
+				}
+			}
+		}
+		fit
+	}
+}
+
+impl ProcessHandler for DelayProcessor {
+	fn process (&mut self, client: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+		#[cfg(feature = "rt-check")]
+		let _guard = rt_check::Guard::enter();
+		self.status.periods.fetch_add(1, Ordering::Relaxed);
+		if !self.failed {
+			// the default panic hook has printed what went wrong by now
+			let period = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.run_period(client, ps)));
+			if period.is_err() {
+				self.failed = true;
+				eprintln!("error: the delay failed and is muted from now on, restart it to continue");
+			}
+		}
+		if self.failed {
+			self.silence(ps);
+		}
+		jack::Control::Continue
+	}
+
+	/// Called before the first period and whenever the period size changes.
+	/// Jack doesn't run the process callback meanwhile, so this may allocate.
+	fn buffer_size (&mut self, _: &jack::Client, size: jack::Frames) -> jack::Control {
+		let size = size as usize;
+		if self.duck_gains.len() < size {
+			self.duck_gains.resize(size, 1.0);
+		}
+		if self.units[0].in_2.is_none() && self.silence.len() < size {
+			self.silence = vec![0.0; size];
+			for unit in &mut self.units {
+				unit.discarded = vec![0.0; size];
+			}
+		}
+		if self.feedback_insert.is_some() {
+			// the returned signal is written back a period behind
+			for line in self.units[0].delay.lines_mut() {
+				line.reserve(size as f64);
+			}
+		}
+		jack::Control::Continue
+	}
+}
+
+/// The process handler with `--matrix`, delays the inputs into the outputs they are routed to.
+struct MatrixProcessor {
+	inputs: Vec<jack::Port<jack::AudioIn>>,
+	outputs: Vec<jack::Port<jack::AudioOut>>,
+	matrix: DelayMatrix,
+	/// runtime commands don't apply to the matrix, they are only taken off the queue
+	commands: QueueReceiver<Control>,
+	status: Arc<Status>,
+	/// share the outputs with `--workers`
+	workers: Option<WorkerPool>,
+	/// a panic left the state undefined, so the outputs stay silent
+	failed: bool,
+}
+
+impl ProcessHandler for MatrixProcessor {
+	fn process (&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+		#[cfg(feature = "rt-check")]
+		let _guard = rt_check::Guard::enter();
+		self.status.periods.fetch_add(1, Ordering::Relaxed);
+		while self.commands.try_recv().is_some() {}
+		if !self.failed {
+			let (inputs, outputs, matrix) = (&self.inputs, &mut self.outputs, &mut self.matrix);
+			let workers = &mut self.workers;
+			let period = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match workers {
+				Some(workers) => {
+					let frames = matrix.write(inputs.iter().map(|port| port.as_slice(ps)));
+					let (ports, reading, scope) = (Shared(outputs.as_mut_ptr()), &*matrix, Shared(ps));
+					workers.run(outputs.len(), &|index| {
+						// every index is run once, so each output is only ever on one thread
+						let port = unsafe { &mut *ports.0.add(index) };
+						reading.read(index, port.as_mut_slice(scope.0), frames);
+					});
+					matrix.advance(frames);
+				}
+				None => matrix.process(inputs.iter().map(|port| port.as_slice(ps)), outputs.iter_mut().map(|port| port.as_mut_slice(ps))),
+			}));
+			if period.is_err() {
+				self.failed = true;
+				eprintln!("error: the matrix failed and is muted from now on, restart it to continue");
+			}
+		}
+		if self.failed {
+			for port in &mut self.outputs {
+				port.as_mut_slice(ps).fill(0.0);
+			}
+		}
+		jack::Control::Continue
+	}
+
+	/// Jack doesn't run the process callback meanwhile, so this may allocate.
+	fn buffer_size (&mut self, _: &jack::Client, size: jack::Frames) -> jack::Control {
+		self.matrix.reserve(size as usize);
+		jack::Control::Continue
+	}
+}
+
+/// The process handler of the client on the `--bridge` server.
+struct BridgeProcessor {
+	outputs: Vec<jack::Port<jack::AudioOut>>,
+	frames: QueueReceiver<[f32; 2]>,
+	resampler: DriftResampler,
+	/// discarded second channel in mono mode, period sized
+	scratch: Vec<f32>,
+}
+
+impl ProcessHandler for BridgeProcessor {
+	fn process (&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+		#[cfg(feature = "rt-check")]
+		let _guard = rt_check::Guard::enter();
+		let queued = self.frames.len();
+		let (first, rest) = self.outputs.split_at_mut(1);
+		let out_1 = first[0].as_mut_slice(ps);
+		let out_2 = match rest.first_mut() {
+			Some(port) => port.as_mut_slice(ps),
+			None => &mut self.scratch,
+		};
+		let frames = &mut self.frames;
+		self.resampler.process(queued, [out_1, out_2], || frames.try_recv());
+		jack::Control::Continue
+	}
+
+	/// Jack doesn't run the process callback meanwhile, so this may allocate.
+	fn buffer_size (&mut self, _: &jack::Client, size: jack::Frames) -> jack::Control {
+		self.scratch = vec![0.0; size as usize];
+		jack::Control::Continue
+	}
+}
+
+/// Passes on when the `--bridge` server goes away.
+struct BridgeNotifications {
+	events: std::sync::mpsc::Sender<Event>,
+}
+
+impl jack::NotificationHandler for BridgeNotifications {
+	fn shutdown(&mut self, _: jack::ClientStatus, reason: &str) {
+		let _ = self.events.send(Event::BridgeShutdown(reason.to_string()));
+	}
+}
+
+/// The client on the `--bridge` server.
+type Bridge = jack::AsyncClient<BridgeNotifications, BridgeProcessor>;
+
+/// Deactivates the bridge client, if there is one.
+fn close_bridge (bridge: Option<Bridge>) {
+	if let Some(bridge) = bridge {
+		if let Err(e) = bridge.deactivate() {
+			eprintln!("failed to deactivate the bridge client: {}", e);
+		}
 	}
 }
 
-/// sends frames from memory vector to output port
-/// if the vector is longer than the specified amount of frames.
-fn send_frames (delay_frames: usize, out_port: &mut[f32], memory: &mut Vec<f32>, flush: &mut bool) {
-	if *flush {
-		if memory.len() > out_port.len() {
-			out_port.clone_from_slice(&memory[..out_port.len()]);
-			*memory = memory[out_port.len()..].into();
-		} else {
-			for _i in 0..(out_port.len() - memory.len()) {
-				memory.push(0.0);
+/// An input port whose signal leaves through an output port.
+struct LatencyPath {
+	input: String,
+	output: String,
+	/// least and most frames the output is reported to be later than the input, negative when it is earlier
+	latency: (i64, i64),
+}
+
+impl LatencyPath {
+	fn new<I, O> (input: &jack::Port<I>, output: &jack::Port<O>, latency: i64) -> Result<Self, Error> {
+		Self::ranged(input, output, (latency, latency))
+	}
+
+	/// A path whose latency varies between `latency.0` and `latency.1`, e.g. while modulated.
+	fn ranged<I, O> (input: &jack::Port<I>, output: &jack::Port<O>, latency: (i64, i64)) -> Result<Self, Error> {
+		Ok(LatencyPath {
+			input: port_name(input)?,
+			output: port_name(output)?,
+			latency,
+		})
+	}
+
+	/// Widens the latency range at the input to the one at the output.
+	fn shift (&self, (min, max): (jack::Frames, jack::Frames)) -> (jack::Frames, jack::Frames) {
+		let shift = |frames: jack::Frames, latency: i64| (frames as i64 + latency).clamp(0, jack::Frames::MAX as i64) as jack::Frames;
+		(shift(min, self.latency.0), shift(max, self.latency.1))
+	}
+}
+
+/// Full name of an own port, including the client name.
+fn port_name<PS> (port: &jack::Port<PS>) -> Result<String, Error> {
+	port.name().map_err(|error| Error::Jack("read a port name", error))
+}
+
+/// Keys of the JACK metadata, see https://jackaudio.org/metadata/
+const PRETTY_NAME: &str = "http://jackaudio.org/metadata/pretty-name";
+const SIGNAL_TYPE: &str = "http://jackaudio.org/metadata/signal-type";
+const ORDER: &str = "http://jackaudio.org/metadata/order";
+/// properties of the client holding the command line and the runtime commands applied since starting
+const ARGUMENTS: &str = "urn:jack-delay:arguments";
+const COMMANDS: &str = "urn:jack-delay:commands";
+
+extern "C" {
+	// part of libjack, which the jack crate links to without wrapping this one
+	fn jack_port_uuid (port: *const libc::c_void) -> u64;
+}
+
+/// Sets a metadata property on `subject`, a failure only gives a warning.
+fn set_property (client: &jack::Client, subject: u64, key: &str, value: &str, typ: Option<&str>) {
+	let property = jack::Property::new(value, typ.map(str::to_string));
+	if let Err(error) = client.property_set(subject, key, &property) {
+		eprintln!("warning: could not set {} to \"{}\": {:?}", key, value, error);
+	}
+}
+
+/// Gives all own ports a pretty name and their order for patchbays, and marks the `cv_ports` as such.
+fn describe_ports (client: &jack::Client, cv_ports: &[String]) {
+	let own = format!("{}:", client.name());
+	let names = client.ports(None, None, jack::PortFlags::empty());
+	for (order, name) in names.iter().filter(|name| name.starts_with(&own)).enumerate() {
+		let port = match client.port_by_name(name) {
+			Some(port) => port,
+			None => continue,
+		};
+		// `port` is one of the own ports, registered until the client closes
+		let uuid = unsafe { jack_port_uuid(port.raw() as *const libc::c_void) };
+		set_property(client, uuid, PRETTY_NAME, &pretty_name(&name[own.len()..]), None);
+		set_property(client, uuid, ORDER, &order.to_string(), Some("http://www.w3.org/2001/XMLSchema#integer"));
+		if cv_ports.contains(name) {
+			set_property(client, uuid, SIGNAL_TYPE, "CV", None);
+		}
+	}
+}
+
+/// Spells out a short port name, e.g. `Front fb send 2` for `front_fb_send2`.
+fn pretty_name (short: &str) -> String {
+	let mut spaced = String::new();
+	for c in short.chars() {
+		// channel numbers become words of their own
+		if c.is_ascii_digit() && spaced.ends_with(char::is_alphabetic) {
+			spaced.push(' ');
+		}
+		spaced.push(if c == '_' { ' ' } else { c });
+	}
+	let words: Vec<String> = spaced.split_whitespace()
+		.enumerate()
+		.map(|(index, word)| match word {
+			"midi" | "cv" => word.to_uppercase(),
+			_ if index == 0 => {
+				let mut chars = word.chars();
+				chars.next().map_or(String::new(), |first| first.to_uppercase().chain(chars).collect())
+			}
+			_ => word.to_string(),
+		})
+		.collect();
+	words.join(" ")
+}
+
+/// Audio ports whose whole name matches the regular expression `pattern`.
+fn matching_ports (client: &jack::Client, pattern: &str, direction: jack::PortFlags) -> Vec<String> {
+	client.ports(Some(&format!("^({})$", pattern)), Some("audio"), direction)
+}
+
+/// What an own port is connected to.
+enum Peer {
+	/// every port matching the pattern
+	Pattern(String),
+	/// the physical port at this position, whatever it is called at the moment
+	Physical(usize),
+}
+
+impl Peer {
+	fn resolve (&self, client: &jack::Client, direction: jack::PortFlags) -> Vec<String> {
+		match self {
+			Peer::Pattern(pattern) => matching_ports(client, pattern, direction),
+			Peer::Physical(index) => physical_ports(client, direction).into_iter().skip(*index).take(1).collect(),
+		}
+	}
+}
+
+/// Own ports paired with the peers to connect them to.
+struct Connections {
+	/// (the sources, own input)
+	inputs: Vec<(Peer, String)>,
+	/// (own output, the destinations)
+	outputs: Vec<(String, Peer)>,
+	own_inputs: Vec<String>,
+	own_outputs: Vec<String>,
+	/// (source, destination) of every connection of the own ports, kept with `--reconnect`
+	remembered: Vec<(String, String)>,
+	verbosity: u8,
+}
+
+impl Connections {
+	/// Whether any own port is connected to anything.
+	fn in_use (&self, client: &jack::Client) -> bool {
+		self.own_inputs.iter().chain(&self.own_outputs).any(|name| {
+			client.port_by_name(name).is_some_and(|port| matches!(port.connected_count(), Ok(count) if count > 0))
+		})
+	}
+
+	/// Connects each own port to its peers as far as they exist right now. Called again
+	/// whenever ports appear, so peers that come back after a replug or a restart are
+	/// connected again and new ones matching a pattern are picked up.
+	fn connect (&self, client: &jack::Client) {
+		for (peer, input) in &self.inputs {
+			for source in peer.resolve(client, jack::PortFlags::IS_OUTPUT) {
+				self.connect_ports(client, &source, input);
+			}
+		}
+		for (output, peer) in &self.outputs {
+			for destination in peer.resolve(client, jack::PortFlags::IS_INPUT) {
+				self.connect_ports(client, output, &destination);
+			}
+		}
+		for (source, destination) in &self.remembered {
+			if client.port_by_name(source).is_some() && client.port_by_name(destination).is_some() {
+				self.connect_ports(client, source, destination);
 			}
+		}
+	}
+
+	/// Takes over the connections the own ports have right now. Those to ports that are gone
+	/// are kept, so they are made again once the ports are back, e.g. after a server restart.
+	fn remember (&mut self, client: &jack::Client) {
+		let existing: std::collections::HashSet<String> = client.ports(None, None, jack::PortFlags::empty()).into_iter().collect();
+		let inputs = self.own_inputs.iter()
+			.flat_map(|input| connections_of(client, input).into_iter().map(move |source| (source, input.clone())));
+		let outputs = self.own_outputs.iter()
+			.flat_map(|output| connections_of(client, output).into_iter().map(move |destination| (output.clone(), destination)));
+		let current: Vec<(String, String)> = inputs.chain(outputs).collect();
+		self.remembered.retain(|(source, destination)| !existing.contains(source) || !existing.contains(destination));
+		self.remembered.extend(current);
+	}
+
+	/// Follows a port to its new name, so the connections remembered for it are made again
+	/// under that name and not kept for the old one that won't come back.
+	fn rename (&mut self, old_name: &str, new_name: &str) {
+		let renamed = |name: String| if name == old_name { new_name.to_string() } else { name };
+		for name in self.own_inputs.iter_mut().chain(&mut self.own_outputs)
+			.chain(self.inputs.iter_mut().map(|(_, input)| input))
+			.chain(self.outputs.iter_mut().map(|(output, _)| output)) {
+			*name = renamed(std::mem::take(name));
+		}
+		for (source, destination) in std::mem::take(&mut self.remembered) {
+			let connection = (renamed(source), renamed(destination));
+			if !self.remembered.contains(&connection) {
+				self.remembered.push(connection);
+			}
+		}
+	}
+
+	/// Connects `source` to `destination` unless they are already, warning
+	/// instead of failing as the client works without.
+	fn connect_ports (&self, client: &jack::Client, source: &str, destination: &str) {
+		if are_connected(client, source, destination) {
+			return;
+		}
+		match client.connect_ports_by_name(source, destination) {
+			Ok(()) if self.verbosity > 0 => println!("connected {} to {}", source, destination),
+			Ok(()) => {}
+			Err(error) => eprintln!("warning: could not connect {} to {}: {:?}", source, destination, error),
+		}
+	}
+}
+
+/// Reads the connections saved with `save_connections`, none if the file doesn't exist yet.
+fn load_connections (path: &std::path::Path) -> Result<Vec<(String, String)>, String> {
+	let text = match std::fs::read_to_string(path) {
+		Ok(text) => text,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => return Err(format!("can't read \"{}\": {}", path.display(), e)),
+	};
+	text.lines()
+		.enumerate()
+		.filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+		.map(|(number, line)| match line.split_once('\t') {
+			Some((source, destination)) => Ok((source.to_string(), destination.to_string())),
+			None => Err(format!("{} line {}: expected \"<source>\\t<destination>\"", path.display(), number + 1)),
+		})
+		.collect()
+}
+
+/// Writes a connection per line as the full names of the source and destination port, separated by a tab
+/// as port names may contain spaces. A failure only gives a warning.
+fn save_connections (path: &std::path::Path, connections: &[(String, String)], verbosity: u8) {
+	let mut text = String::from("# jack-delay connections, <source>\t<destination>\n");
+	for (source, destination) in connections {
+		text.push_str(&format!("{}\t{}\n", source, destination));
+	}
+	match std::fs::write(path, text) {
+		Ok(()) if verbosity > 0 => println!("saved {} connection(s) to {}", connections.len(), path.display()),
+		Ok(()) => {}
+		Err(e) => eprintln!("warning: could not save the connections to \"{}\": {}", path.display(), e),
+	}
+}
+
+fn are_connected (client: &jack::Client, source: &str, destination: &str) -> bool {
+	client.port_by_name(source).is_some_and(|port| port.is_connected_to(destination).unwrap_or(false))
+}
+
+extern "C" {
+	// part of libjack, which the jack crate links to without wrapping these
+	fn jack_port_get_connections (port: *const libc::c_void) -> *mut *const libc::c_char;
+	fn jack_free (ptr: *mut libc::c_void);
+}
+
+/// The full names of the ports the own port `name` is connected to, none if it is gone.
+fn connections_of (client: &jack::Client, name: &str) -> Vec<String> {
+	let port = match client.port_by_name(name) {
+		Some(port) => port,
+		None => return vec![],
+	};
+	let mut names = vec![];
+	// `port` is one of the own ports, and the list ends with a null pointer unless it is null itself
+	unsafe {
+		let list = jack_port_get_connections(port.raw() as *const libc::c_void);
+		if list.is_null() {
+			return names;
+		}
+		let mut entry = list;
+		while !(*entry).is_null() {
+			names.push(std::ffi::CStr::from_ptr(*entry).to_string_lossy().into_owned());
+			entry = entry.add(1);
+		}
+		jack_free(list as *mut libc::c_void);
+	}
+	names
+}
+
+/// What the main thread waits for while the client is running.
+enum Event {
+	/// a shutdown signal arrived
+	Shutdown(libc::c_int),
+	/// the server shut down or closed the client, for the reason it gave
+	ServerShutdown(String),
+	/// some client registered a port, which may match a connection
+	PortRegistered,
+	/// a port was renamed from the first name to the second
+	PortRenamed(String, String),
+	/// some ports were connected or disconnected
+	ConnectionsChanged,
+	/// a runtime command was applied, as it was typed
+	Command(String),
+	/// the `--bridge` server shut down or closed the client there, for the reason it gave
+	BridgeShutdown(String),
+	/// the `save` command asks to write the connections to the `--connections` file
+	SaveConnections,
+}
+
+/// How serving a client ended.
+enum Ended {
+	/// on a shutdown signal, after deactivating the client
+	Signal,
+	/// with the reason the server gave
+	ServerShutdown(String),
+}
+
+/// Range of sample rates servers run at, for checking the arguments before connecting.
+const LOWEST_SAMPLE_RATE: usize = 8000;
+const HIGHEST_SAMPLE_RATE: usize = 384_000;
+
+/// How often to look for the server while it's gone.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The physical audio ports of the server, by default in the order of the hardware channels.
+/// `jack::PortFlags::IS_OUTPUT` gives the capture ports, `IS_INPUT` the playback ports.
+fn physical_ports (client: &jack::Client, direction: jack::PortFlags) -> Vec<String> {
+	client.ports(None, Some("audio"), jack::PortFlags::IS_PHYSICAL | direction)
+}
+
+/// Reasons the client can't start, each with its own exit code.
+/// Invalid arguments exit with 1.
+#[derive(Debug)]
+enum Error {
+	/// with the name of the server looked for unless it's the default one
+	ServerNotRunning(Option<String>),
+	/// the server didn't accept the client
+	ClientRejected(jack::ClientStatus),
+	PortRegistration(String),
+	/// any other failing call, with what it was supposed to do
+	Jack(&'static str, jack::Error),
+	/// the file for a delay buffer couldn't be set up
+	DiskBuffer(std::path::PathBuf, std::io::Error),
+	/// the process callback stopped being called for this long
+	Stalled(Duration),
+	/// none of the ports was connected for this long with `--exit-if-unused`
+	Unused(Duration),
+	/// the server shut down or closed the client, for this reason
+	Closed(String),
+}
+
+impl Error {
+	fn exit_code (&self) -> i32 {
+		match self {
+			Error::ServerNotRunning(_) => 2,
+			Error::ClientRejected(_) => 3,
+			Error::PortRegistration(_) => 4,
+			Error::Jack(_, _) => 5,
+			Error::DiskBuffer(_, _) => 6,
+			Error::Stalled(_) => 7,
+			Error::Unused(_) => 8,
+			Error::Closed(_) => 9,
+		}
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt (&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Error::ServerNotRunning(None) => write!(f, "the JACK server is not running, start it first (e.g. with qjackctl or jackd) or pass --start-server"),
+			Error::ServerNotRunning(Some(server)) => write!(f, "the JACK server \"{}\" is not running, start it first (e.g. with jackd -n {}) or pass --start-server", server, server),
+			Error::ClientRejected(status) => write!(f, "the JACK server did not accept the client ({:?})", status),
+			Error::PortRegistration(name) => write!(f, "could not register the port \"{}\"", name),
+			Error::Jack(action, error) => write!(f, "failed to {}: {:?}", action, error),
+			Error::DiskBuffer(path, error) => write!(f, "could not keep a delay buffer in {}: {}", path.display(), error),
+			Error::Stalled(timeout) => write!(f, "JACK stopped processing the client for {:?}, the server hung or dropped it", timeout),
+			Error::Unused(timeout) => write!(f, "none of the ports was connected for {:?}", timeout),
+			Error::Closed(reason) => write!(f, "the JACK server closed the client: {}", reason),
+		}
+	}
+}
 
-			out_port.clone_from_slice(&memory.as_slice());
-			memory.clear();
-			*flush = false;
+/// Connects to the server, which is only started by this client if `start_server` is set.
+fn open_client (name: &str, start_server: bool) -> Result<jack::Client, Error> {
+	let options = if start_server { jack::ClientOptions::empty() } else { jack::ClientOptions::NO_START_SERVER };
+	match jack::Client::new(name, options) {
+		Ok((client, _status)) => Ok(client),
+		Err(jack::Error::ClientError(status)) if status.contains(jack::ClientStatus::SERVER_FAILED) => {
+			// set by --server, or by the user
+			Err(Error::ServerNotRunning(std::env::var("JACK_DEFAULT_SERVER").ok()))
 		}
-	} else if memory.len() >= delay_frames {
-		*flush = true;
-	} 
+		Err(jack::Error::ClientError(status)) => Err(Error::ClientRejected(status)),
+		Err(error) => Err(Error::Jack("open the client", error)),
+	}
+}
+
+extern "C" {
+	// the jack crate has no way to hand the server name to jack_client_open
+	fn jack_client_open (name: *const libc::c_char, options: libc::c_uint, status: *mut libc::c_uint, ...) -> *mut libc::c_void;
+}
+
+/// Opens a client on the server named `server` rather than the default one, never starting it.
+fn open_client_on (name: &str, server: &str) -> Result<jack::Client, Error> {
+	// neither comes with a NUL byte from the command line
+	let (name, server_name) = (std::ffi::CString::new(name).unwrap(), std::ffi::CString::new(server).unwrap());
+	let options = (jack::ClientOptions::NO_START_SERVER | jack::ClientOptions::SERVER_NAME).bits();
+	let mut status = 0;
+	let client = unsafe { jack_client_open(name.as_ptr(), options, &mut status, server_name.as_ptr()) };
+	let status = jack::ClientStatus::from_bits_truncate(status);
+	if client.is_null() {
+		return Err(if status.contains(jack::ClientStatus::SERVER_FAILED) {
+			Error::ServerNotRunning(Some(server.to_string()))
+		} else {
+			Error::ClientRejected(status)
+		});
+	}
+	// the pointer came from jack_client_open and nothing else holds it
+	Ok(unsafe { jack::Client::from_raw(client as *mut _) })
+}
+
+fn register_port<PS: jack::PortSpec> (client: &jack::Client, name: &str, spec: PS) -> Result<jack::Port<PS>, Error> {
+	client.register_port(name, spec).map_err(|_| Error::PortRegistration(name.to_string()))
 }
 
 /// Handles printing notifications from Jack.
-/// The const generic parameter specifies the verbosity of output.
-struct Notifications<const V: u8>;
+struct Notifications {
+	/// 0 prints nothing but thread start and shutdown, 1 adds
+	/// changes of the server state and 2 changes of the graph
+	verbosity: u8,
+	latency_paths: Vec<LatencyPath>,
+	/// passes sample rate changes on to the process callback
+	sample_rate: Arc<AtomicUsize>,
+	status: Arc<Status>,
+	events: std::sync::mpsc::Sender<Event>,
+}
 
-impl <const V:u8> jack::NotificationHandler for Notifications<V> {
+impl jack::NotificationHandler for Notifications {
 	fn thread_init(&self, _: &jack::Client) {
 		println!("JACK: thread init");
 	}
@@ -52,26 +2473,31 @@ impl <const V:u8> jack::NotificationHandler for Notifications<V> {
 			"JACK: shutdown with status {:?} because \"{}\"",
 			status, reason
 		);
+		// whether to exit or wait for the server is up to the main thread
+		let _ = self.events.send(Event::ServerShutdown(reason.to_string()));
 	}
 
 	fn freewheel(&mut self, _: &jack::Client, is_enabled: bool) {
-		if V > 0 {
+		if self.verbosity > 0 {
 			println!(
 				"JACK: freewheel mode is {}",
 				if is_enabled { "on" } else { "off" }
 			);
 		}
+		self.status.freewheeling.store(is_enabled, Ordering::Relaxed);
 	}
 
 	fn sample_rate(&mut self, _: &jack::Client, srate: jack::Frames) -> jack::Control {
-		if V > 0 {
+		if self.verbosity > 0 {
 			println!("JACK: sample rate changed to {}", srate);
 		}
+		// the process callback picks it up with its next period
+		self.sample_rate.store(srate as usize, Ordering::Relaxed);
 		jack::Control::Continue
 	}
 
 	fn client_registration(&mut self, _: &jack::Client, name: &str, is_reg: bool) {
-		if V > 1 {
+		if self.verbosity > 1 {
 			println!(
 				"JACK: {} client with name \"{}\"",
 				if is_reg { "registered" } else { "unregistered" },
@@ -81,13 +2507,17 @@ impl <const V:u8> jack::NotificationHandler for Notifications<V> {
 	}
 
 	fn port_registration(&mut self, _: &jack::Client, port_id: jack::PortId, is_reg: bool) {
-		if V > 1 {
+		if self.verbosity > 1 {
 			println!(
 				"JACK: {} port with id {}",
 				if is_reg { "registered" } else { "unregistered" },
 				port_id
 			);
 		}
+		if is_reg {
+			// connecting from within a notification isn't allowed, the main thread does it
+			let _ = self.events.send(Event::PortRegistered);
+		}
 	}
 
 	fn port_rename(
@@ -97,12 +2527,13 @@ impl <const V:u8> jack::NotificationHandler for Notifications<V> {
 		old_name: &str,
 		new_name: &str,
 	) -> jack::Control {
-		if V > 1 {
+		if self.verbosity > 1 {
 			println!(
 				"JACK: port with id {} renamed from {} to {}",
 				port_id, old_name, new_name
 			);
 		}
+		let _ = self.events.send(Event::PortRenamed(old_name.to_string(), new_name.to_string()));
 		jack::Control::Continue
 	}
 
@@ -113,7 +2544,7 @@ impl <const V:u8> jack::NotificationHandler for Notifications<V> {
 		port_id_b: jack::PortId,
 		are_connected: bool,
 	) {
-		if V > 1 {
+		if self.verbosity > 1 {
 				println!(
 				"JACK: ports with id {} and {} are {}",
 				port_id_a,
@@ -125,24 +2556,27 @@ impl <const V:u8> jack::NotificationHandler for Notifications<V> {
 				}
 			);
 		}
+		if !self.status.connections_changed.swap(true, Ordering::Relaxed) {
+			let _ = self.events.send(Event::ConnectionsChanged);
+		}
 	}
 
 	fn graph_reorder(&mut self, _: &jack::Client) -> jack::Control {
-		if V > 1 {
+		if self.verbosity > 1 {
 			println!("JACK: graph reordered");
 		}
 		jack::Control::Continue
 	}
 
 	fn xrun(&mut self, _: &jack::Client) -> jack::Control {
-		if V > 0 {
+		if self.verbosity > 0 {
 			println!("JACK: xrun occurred");
 		}
 		jack::Control::Continue
 	}
 
-	fn latency(&mut self, _: &jack::Client, mode: jack::LatencyType) {
-		if V > 1 {
+	fn latency(&mut self, client: &jack::Client, mode: jack::LatencyType) {
+		if self.verbosity > 1 {
 			println!(
 				"JACK: {} latency has changed",
 				match mode {
@@ -151,75 +2585,855 @@ impl <const V:u8> jack::NotificationHandler for Notifications<V> {
 				}
 			);
 		}
+		// capture latency flows from the inputs to the outputs, playback latency the other way.
+		// latencies can't go below zero, so only latency already in the graph can be compensated.
+		let mut ranges: Vec<(&str, (jack::Frames, jack::Frames))> = vec![];
+		for path in &self.latency_paths {
+			let (source, target) = match mode {
+				jack::LatencyType::Capture => (&path.input, &path.output),
+				jack::LatencyType::Playback => (&path.output, &path.input),
+			};
+			let (min, max) = match client.port_by_name(source) {
+				Some(port) => port.get_latency_range(mode),
+				None => continue,
+			};
+			let range = path.shift((min, max));
+			match ranges.iter_mut().find(|(name, _)| name == target) {
+				Some((_, existing)) => *existing = (existing.0.min(range.0), existing.1.max(range.1)),
+				None => ranges.push((target, range)),
+			}
+		}
+		for (name, range) in ranges {
+			if let Some(port) = client.port_by_name(name) {
+				port.set_latency_range(mode, range);
+			}
+		}
 	}
 }
 
-/// This is a setup for some horrible code later on
-// TODO: find a better way to handle verbosity
-enum ClientDump <P: Send + ProcessHandler, const A: u8, const B: u8, const C: u8>{
-	ACli(AsyncClient<Notifications::<A>, P>),
-	BCli(AsyncClient<Notifications::<B>, P>),
-	CCli(AsyncClient<Notifications::<C>, P>),
+fn main() {
+	if let Err(error) = run() {
+		eprintln!("error: {}", error);
+		std::process::exit(error.exit_code());
+	}
 }
 
-fn main() {
+fn run () -> Result<(), Error> {
 
 	let args = Args::from_args();
 
-	println!("Verbosity: {}, buffer: {}", args.verbosity, args.delay_frames);
-
-	let v = args.verbosity;
-
-	let (jack_client, _status) =
-		jack::Client::new("rust_delay", jack::ClientOptions::NO_START_SERVER).unwrap();
-	let in_1 = jack_client
-		.register_port("in1", jack::AudioIn::default())
-		.unwrap();
-	let in_2 = jack_client
-		.register_port("in2", jack::AudioIn::default())
-		.unwrap();
-	let mut out_1 = jack_client
-		.register_port("out1", jack::AudioOut::default())
-		.unwrap();
-	let mut out_2 = jack_client
-		.register_port("out2", jack::AudioOut::default())
-		.unwrap();
-	
-	let mut flush = false;
-	let mut mem1 = vec!();
-	let mut mem2 = vec!();
-
-	let process = jack::ClosureProcessHandler::new(
-		// this closure gets called repeatedly to handle the audio frames.
-		move | _: &jack::Client, ps: &jack::ProcessScope | -> jack::Control {
-		let in_1_p = in_1.as_slice(ps);
-		let in_2_p = in_2.as_slice(ps);
-		let out_1_p = out_1.as_mut_slice(ps);
-		let out_2_p = out_2.as_mut_slice(ps);
-
-		receive_frames(in_1_p, &mut mem1);
-		receive_frames(in_2_p, &mut mem2);
-		send_frames(args.delay_frames, out_1_p, &mut mem1, &mut flush);
-		send_frames(args.delay_frames, out_2_p, &mut mem2, &mut flush);
-		jack::Control::Continue
+	// the jack crate can't hand a server name to jack_client_open, which falls back to this variable.
+	// setting it is only sound while there is no other thread yet.
+	if let Some(server) = &args.server {
+		std::env::set_var("JACK_DEFAULT_SERVER", server);
+	}
+
+	// before any other thread is started, jack's own included
+	let shutdown_signals = block_shutdown_signals();
+
+	// invalid arguments are reported as such rather than as a server that isn't running
+	if let Err(message) = args.validate(None) {
+		structopt::clap::Error::with_description(&message, structopt::clap::ErrorKind::ValueValidation).exit();
+	}
+	// connected along with those to connect to, and kept up to date from then on
+	let mut remembered = match &args.connections {
+		Some(path) => match load_connections(path) {
+			Ok(connections) => connections,
+			Err(message) => structopt::clap::Error::with_description(&message, structopt::clap::ErrorKind::ValueValidation).exit(),
+		},
+		None => vec![],
+	};
+
+	let mut jack_client = open_client("rust_delay", args.start_server)?;
+
+	let channels = args.channels();
+	// everything from here on outlives the client, which is set up anew whenever the server restarts
+	let (command_tx, mut command_rx) = queue(64);
+	let commands = Arc::new(Mutex::new(command_tx));
+	let status = Arc::new(Status::default());
+	let limiting = args.limit.is_some();
+	let control_status = status.clone();
+	let setups = args.unit_setups();
+	*status.meters.lock().unwrap() = vec![Default::default(); setups.len()];
+	let units = args.units.iter().map(|unit| unit.name.clone()).collect();
+	// the matrix isn't metered
+	let outputs = match args.matrix {
+		Some(_) => vec![],
+		None => setups.iter()
+			.map(|(names, _)| (0..channels).map(|channel| names.channel("out", channel)).collect())
+			.collect(),
+	};
+	let (events, event_rx) = std::sync::mpsc::channel();
+	let control_commands = commands.clone();
+	let control_events = events.clone();
+	std::thread::spawn(move || control_loop(control_commands, control_status, limiting, units, outputs, control_events));
+
+	if args.watchdog > 0.0 {
+		let status = status.clone();
+		let timeout = Duration::from_secs_f64(args.watchdog);
+		std::thread::spawn(move || watchdog(status, timeout));
+	}
+
+	let shutdown_events = events.clone();
+	std::thread::spawn(move || {
+		let signal = wait_for_shutdown(&shutdown_signals);
+		let _ = shutdown_events.send(Event::Shutdown(signal));
+	});
+
+	loop {
+		match serve(&args, jack_client, command_rx, &status, &events, &event_rx, &mut remembered)? {
+			Ended::Signal => return Ok(()),
+			Ended::ServerShutdown(_) if args.reconnect => {}
+			// so a supervisor restarts the client
+			Ended::ServerShutdown(reason) => return Err(Error::Closed(reason)),
+		}
+		println!("waiting for the JACK server to come back");
+		jack_client = loop {
+			match event_rx.recv_timeout(RECONNECT_INTERVAL) {
+				Ok(Event::Shutdown(signal)) => {
+					if args.verbosity > 0 {
+						println!("shutting down on signal {}", signal);
+					}
+					return Ok(());
+				}
+				// left over from the old client
+				Ok(_) => continue,
+				Err(_) => {}
+			}
+			// starting a server of its own would get in the way of the one being restarted
+			match open_client("rust_delay", false) {
+				Ok(client) => break client,
+				Err(Error::ServerNotRunning(_)) => {}
+				Err(error) => return Err(error),
+			}
+		};
+		// the receiving half went down with the old client, the control thread sends to a new one
+		let (command_tx, next_rx) = queue(64);
+		*commands.lock().unwrap() = command_tx;
+		command_rx = next_rx;
+	}
+}
+
+/// Sets up the ports and the delay or the matrix on `jack_client` following `args`,
+/// activates it and runs it until a signal arrives or the server shuts down.
+fn serve (
+	args: &Args,
+	jack_client: jack::Client,
+	command_rx: QueueReceiver<Control>,
+	status: &Arc<Status>,
+	events: &std::sync::mpsc::Sender<Event>,
+	event_rx: &std::sync::mpsc::Receiver<Event>,
+	remembered: &mut Vec<(String, String)>,
+) -> Result<Ended, Error> {
+	if let Err(message) = args.validate(Some(jack_client.sample_rate())) {
+		structopt::clap::Error::with_description(&message, structopt::clap::ErrorKind::ValueValidation).exit();
+	}
+	match &args.matrix {
+		Some(routing) => {
+			let setup = set_up_matrix(args, &jack_client, routing, command_rx, status)?;
+			activate(args, jack_client, setup, status, events, event_rx, remembered)
+		}
+		None => {
+			let mut setup = set_up_delay(args, &jack_client, command_rx, status)?;
+			if let Some(server) = &args.bridge {
+				let (bridge, frames) = set_up_bridge(args, server, &jack_client, events)?;
+				setup.process.bridge = Some(frames);
+				setup.bridge = Some(bridge);
+			}
+			activate(args, jack_client, setup, status, events, event_rx, remembered)
+		}
+	}
+}
+
+/// A process handler ready to be activated, with what the client needs to know about its ports.
+struct Setup<P> {
+	process: P,
+	latency_paths: Vec<LatencyPath>,
+	own_inputs: Vec<String>,
+	own_outputs: Vec<String>,
+	/// ports carrying control voltage rather than audio
+	cv_ports: Vec<String>,
+	sample_rate: Arc<AtomicUsize>,
+	/// the client on the `--bridge` server, already running
+	bridge: Option<Bridge>,
+}
+
+/// Opens a client on `server`, registers the outputs there once more and activates it. Returns
+/// it along with the queue for the frames of the delay, which it resamples to the clock of its server.
+fn set_up_bridge (
+	args: &Args,
+	server: &str,
+	jack_client: &jack::Client,
+	events: &std::sync::mpsc::Sender<Event>,
+) -> Result<(Bridge, QueueSender<[f32; 2]>), Error> {
+	let bridge_client = open_client_on("rust_delay", server)?;
+	let names = args.port_names();
+	let outputs = (0..args.channels())
+		.map(|channel| register_port(&bridge_client, &names.channel("out", channel), jack::AudioOut))
+		.collect::<Result<Vec<_>, _>>()?;
+	// room for a period of each side and the jitter between them
+	let target = 2 * (jack_client.buffer_size() + bridge_client.buffer_size()) as usize;
+	let (sender, frames) = queue((target * 8).next_power_of_two());
+	let process = BridgeProcessor {
+		outputs,
+		frames,
+		resampler: DriftResampler::new(jack_client.sample_rate(), bridge_client.sample_rate(), target),
+		scratch: vec![0.0; bridge_client.buffer_size() as usize],
+	};
+	let bridge = bridge_client
+		.activate_async(BridgeNotifications { events: events.clone() }, process)
+		.map_err(|error| Error::Jack("activate the bridge client", error))?;
+	if args.connect {
+		let client = bridge.as_client();
+		let physical = client.ports(None, Some("audio"), jack::PortFlags::IS_PHYSICAL | jack::PortFlags::IS_INPUT);
+		for (channel, destination) in physical.iter().enumerate().take(args.channels()) {
+			let source = format!("{}:{}", client.name(), names.channel("out", channel));
+			if let Err(error) = client.connect_ports_by_name(&source, destination) {
+				eprintln!("warning: could not connect {} to {} on {}: {:?}", source, destination, server, error);
+			}
+		}
+	}
+	Ok((bridge, sender))
+}
+
+/// Registers the ports of the delay units and sets up their delays.
+fn set_up_delay (args: &Args, jack_client: &jack::Client, command_rx: QueueReceiver<Control>, status: &Arc<Status>) -> Result<Setup<DelayProcessor>, Error> {
+	let channels = args.channels();
+	let names = args.port_names();
+	let setups = args.unit_setups();
+	let taps = args.unit_taps();
+
+	if args.units.is_empty() {
+		println!("Verbosity: {}, taps (delay, gain, pan): {:?}", args.verbosity, taps[0]);
+	} else {
+		println!("Verbosity: {}", args.verbosity);
+		for (unit, taps) in args.units.iter().zip(&taps) {
+			println!("unit {}, taps (delay, gain, pan): {:?}", unit.name, taps);
+		}
+	}
+
+	let mut ports = vec![];
+	for (names, _) in &setups {
+		let in_1 = register_port(jack_client, &names.channel("in", 0), jack::AudioIn)?;
+		let out_1 = register_port(jack_client, &names.channel("out", 0), jack::AudioOut)?;
+		let (in_2, out_2) = if args.mono {
+			(None, None)
+		} else {
+			(
+				Some(register_port(jack_client, &names.channel("in", 1), jack::AudioIn)?),
+				Some(register_port(jack_client, &names.channel("out", 1), jack::AudioOut)?),
+			)
+		};
+		ports.push((in_1, in_2, out_1, out_2));
+	}
+
+	let dry_outputs = if args.dry_outputs {
+		Some([
+			register_port(jack_client, &names.channel("dry", 0), jack::AudioOut)?,
+			register_port(jack_client, &names.channel("dry", 1), jack::AudioOut)?,
+		])
+	} else {
+		None
+	};
+
+	let monitor = match args.monitor {
+		Some(source) => Some(Monitor::new([
+			register_port(jack_client, &names.channel("monitor", 0), jack::AudioOut)?,
+			register_port(jack_client, &names.channel("monitor", 1), jack::AudioOut)?,
+		], source)),
+		None => None,
+	};
+
+	let feedback_insert = if args.feedback_insert {
+		Some(InsertPorts {
+			sends: [
+				register_port(jack_client, &names.channel("fb_send", 0), jack::AudioOut)?,
+				register_port(jack_client, &names.channel("fb_send", 1), jack::AudioOut)?,
+			],
+			returns: [
+				register_port(jack_client, &names.channel("fb_return", 0), jack::AudioIn)?,
+				register_port(jack_client, &names.channel("fb_return", 1), jack::AudioIn)?,
+			],
+		})
+	} else {
+		None
+	};
+
+	// the delay may be given in time units, so it can only be resolved
+	// once the sample rate of the server is known.
+	let sample_rate = jack_client.sample_rate();
+
+	let cv = if args.cv { Some(register_port(jack_client, &names.single("cv"), jack::AudioIn)?) } else { None };
+	let cv_ports = cv.iter().map(port_name).collect::<Result<Vec<_>, _>>()?;
+	let sidechain = match args.duck_threshold {
+		Some(threshold) => {
+			let port = register_port(jack_client, &names.single("sidechain"), jack::AudioIn)?;
+			let ducker = Ducker::new(threshold, args.duck_depth, args.duck_attack, args.duck_release, sample_rate);
+			Some((port, ducker))
+		}
+		None => None,
+	};
+	let timebase = Timebase {
+		sample_rate,
+		tempo: Tempo { bpm: args.bpm, beat_type: 4.0 },
+		speed_of_sound: speed_of_sound(args.temperature, args.humidity),
+	};
+	// negative delays are made up for by delaying everything
+	// by the same amount and reporting that much less latency
+	let compensation = taps.iter()
+		.flatten()
+		.flatten()
+		.map(|&(delay, _, _)| -delay.to_frames(&timebase))
+		.fold(0.0, f64::max)
+		.ceil();
+	let haas = args.haas.map_or(0.0, |haas| millis_to_frames(haas, sample_rate));
+
+	let fade_frames = millis_to_frames(args.glide, sample_rate).round() as usize;
+	let build_lines = |unit: usize, taps: &[Vec<(DelayTime, f32, f32)>; 2]| -> Result<[DelayLine; 2], Error> {
+		let mut lines = [DelayLine::new(fade_frames), DelayLine::new(fade_frames)];
+		for (channel, (line, taps)) in lines.iter_mut().zip(taps).enumerate().take(channels) {
+			if let Some(directory) = &args.disk_buffer {
+				let path = directory.join(format!("jack-delay-{}-{}.raw", std::process::id(), unit * 2 + channel));
+				line.set_buffer_file(&path).map_err(|error| Error::DiskBuffer(path, error))?;
+			}
+			line.set_offset(if channel == 1 { compensation + haas } else { compensation });
+			for &(delay, gain, pan) in taps {
+				line.add_tap(delay.to_frames(&timebase), gain, pan);
+			}
+			line.set_feedback(if channel == 1 { args.feedback_right.unwrap_or(args.feedback) } else { args.feedback });
+			line.set_cross_feedback(args.cross_feedback);
+			line.set_feedback_filters(args.fb_lowpass, args.fb_highpass, sample_rate);
+			line.set_wet_filters(args.wet_lowpass, args.wet_highpass, sample_rate);
+			line.set_saturation(args.saturation);
+			line.set_shimmer(args.shimmer, sample_rate);
+			line.set_feedback_ceiling(args.feedback_ceiling, sample_rate);
+			line.set_crusher(args.crush_bits, args.crush_rate, sample_rate);
+			line.set_dc_blocking(args.dc_block, sample_rate);
+			line.set_mix(args.mix);
+			let phase = channel as f64 * args.mod_phase / 360.0;
+			let lfo = Lfo::new(args.mod_rate, args.mod_shape, phase, sample_rate);
+			line.set_modulation(lfo, millis_to_frames(args.mod_depth, sample_rate));
+			if args.cv {
+				line.set_cv(millis_to_frames(args.cv_depth, sample_rate), millis_to_frames(args.cv_offset, sample_rate));
+			}
+			line.set_humanize(args.humanize.map(|ms| millis_to_frames(ms, sample_rate)), 0x68e3_1da4 + channel as u32);
+			if args.wow.is_some() || args.flutter.is_some() {
+				let [wow, flutter] = [args.wow, args.flutter].map(|ms| millis_to_frames(ms.unwrap_or(0.0), sample_rate));
+				// both channels run over the same transport
+				line.set_wow_flutter(Some(WowFlutter::new(wow, flutter, 0x1b87_3593, sample_rate)));
+			}
+			line.set_change_mode(args.change_mode);
+			line.set_interpolation(args.interp);
+			line.set_reverse(args.reverse);
+			if !args.band_delays.is_empty() {
+				let delays: Vec<usize> = args.band_delays.iter().map(|delay| delay.to_frames(&timebase).round() as usize).collect();
+				line.set_bands(Some(BandSplitter::new(&args.crossovers, &delays, sample_rate)));
+			}
+			line.set_shuffle(args.shuffle.map(|ms| millis_to_frames(ms, sample_rate).round() as usize));
+			// the buffer is allocated once here, runtime changes only move within it
+			if let Some(max_delay) = args.max_delay {
+				line.reserve(max_delay.to_frames(&timebase));
+			}
+			if let Some(automation) = &args.automation {
+				let heads = if args.heads.is_some() { HEADS } else { 1 };
+				for &(_, delay) in &automation.breakpoints {
+					line.reserve(delay.scaled(heads as f64).to_frames(&timebase));
+				}
+			}
+		}
+		lines[0].set_inverted(args.invert_left);
+		lines[1].set_inverted(args.invert_right);
+		for line in &mut lines {
+			if let Some(pager) = line.pager() {
+				std::thread::spawn(move || pager.run(sample_rate));
+			}
+		}
+		Ok(lines)
+	};
+
+	// with --report-latency the first tap counts as latency, so hosts compensate for it.
+	// otherwise the delay is an effect and only the compensation for negative delays is reported.
+	// the modulation moves the taps around that, so the range it covers is reported unless given
+	let cv_shift = if args.cv { [args.cv_offset - args.cv_depth.abs(), args.cv_offset + args.cv_depth.abs()] } else { [0.0; 2] };
+	let swing = args.mod_depth + args.wow.unwrap_or(0.0) + args.flutter.unwrap_or(0.0);
+	let modulated = [
+		millis_to_frames(cv_shift[0] - swing, sample_rate),
+		millis_to_frames(cv_shift[1] + swing + args.humanize.unwrap_or(0.0), sample_rate),
+	];
+	let latency = |taps: &[Vec<(DelayTime, f32, f32)>; 2], channel: usize| -> (i64, i64) {
+		if !args.report_latency {
+			return (-(compensation as i64), -(compensation as i64));
+		}
+		let offset = if channel == 1 { haas } else { 0.0 };
+		match args.latency_ranges.get(channel).or_else(|| args.latency_ranges.first()) {
+			Some(range) => (
+				(range.min.to_frames(&timebase) + offset).round() as i64,
+				(range.max.to_frames(&timebase) + offset).round() as i64,
+			),
+			None => {
+				let first = taps[channel].first().map_or(0.0, |&(delay, _, _)| delay.to_frames(&timebase)) + offset;
+				((first + modulated[0]).floor() as i64, (first + modulated[1]).ceil() as i64)
+			}
+		}
+	};
+	let mut latency_paths = vec![];
+	for ((in_1, in_2, out_1, out_2), taps) in ports.iter().zip(&taps) {
+		latency_paths.push(LatencyPath::ranged(in_1, out_1, latency(taps, 0))?);
+		if let (Some(in_2), Some(out_2)) = (in_2, out_2) {
+			latency_paths.push(LatencyPath::ranged(in_2, out_2, latency(taps, 1))?);
+			if let Some([dry_1, dry_2]) = &dry_outputs {
+				latency_paths.push(LatencyPath::new(in_1, dry_1, 0)?);
+				latency_paths.push(LatencyPath::new(in_2, dry_2, 0)?);
+			}
+			// anything from the dry signal to the delayed one, depending on the source
+			if let Some(Monitor { ports: [monitor_1, monitor_2], .. }) = &monitor {
+				latency_paths.push(LatencyPath::ranged(in_1, monitor_1, (0, latency(taps, 0).1))?);
+				latency_paths.push(LatencyPath::ranged(in_2, monitor_2, (0, latency(taps, 1).1))?);
+			}
 		}
-	);
+	}
+
+	let midi = if args.midi {
+		let midi = MidiPorts {
+			input: register_port(jack_client, &names.single("midi_in"), jack::MidiIn)?,
+			output: register_port(jack_client, &names.single("midi_out"), jack::MidiOut)?,
+			delay: MidiDelay::new(0, MIDI_ROOM.0, MIDI_ROOM.1),
+			time: args.midi_delay,
+			compensation,
+			throw_note: args.throw_note,
+		};
+		// reported the same way as for the audio
+		let latency = if args.report_latency {
+			let time = args.midi_delay.or_else(|| taps[0][0].first().map(|&(delay, _, _)| delay));
+			time.map_or(0.0, |time| time.to_frames(&timebase).max(-compensation)).round() as i64
+		} else {
+			-(compensation as i64)
+		};
+		latency_paths.push(LatencyPath::new(&midi.input, &midi.output, latency)?);
+		Some(midi)
+	} else {
+		None
+	};
+
+	let sample_rate_changes = Arc::new(AtomicUsize::new(sample_rate));
+
+	// gains are set before the smoothing, so they start out where they are set to
+	let build_delay = |lines| {
+		let mut delay = StereoDelay::new(lines);
+		delay.set_input_gain(db_to_gain(args.input_gain));
+		delay.set_output_gain(db_to_gain(args.output_gain));
+		delay.set_gate(args.gate_threshold.map(|threshold| Gate::new(threshold, args.gate_attack, args.gate_release, sample_rate)));
+		delay.set_limiter(args.limit.map(|ceiling| SoftLimiter::new(ceiling, sample_rate)));
+		delay.set_send(if args.throw { 0.0 } else { 1.0 });
+		delay.set_swell(args.swell.map(|ms| Swell::new(ms, sample_rate)));
+		delay.set_mid_side(args.mid_side);
+		delay.set_swap(args.swap);
+		delay.set_fade_in(millis_to_frames(args.fade_in, sample_rate).round() as usize);
+		delay.set_smoothing(smoothing_coefficient(args.smoothing, sample_rate));
+		delay
+	};
+
+	// the ports move into the process callback, their names are needed for connecting them later on
+	let own_inputs = ports.iter()
+		.flat_map(|(in_1, in_2, _, _)| std::iter::once(in_1).chain(in_2))
+		.map(port_name)
+		.collect::<Result<Vec<_>, _>>()?;
+	let own_outputs = ports.iter()
+		.flat_map(|(_, _, out_1, out_2)| std::iter::once(out_1).chain(out_2))
+		.map(port_name)
+		.collect::<Result<Vec<_>, _>>()?;
+
+	let mut units = vec![];
+	for (index, ((in_1, in_2, out_1, out_2), taps)) in ports.into_iter().zip(&taps).enumerate() {
+		units.push(Unit {
+			in_1,
+			in_2,
+			out_1,
+			out_2,
+			delay: build_delay(build_lines(index, taps)?),
+			tap_times: [0, 1].map(|channel| taps[channel].iter().map(|&(delay, _, _)| delay).collect()),
+			bypassed: false,
+			// sized in the buffer size callback
+			discarded: vec![],
+		});
+	}
+
+	let process = DelayProcessor {
+		units,
+		// sized in the buffer size callback
+		silence: vec![],
+		workers: args.workers.filter(|&workers| workers > 0).map(WorkerPool::new),
+		dry_outputs,
+		monitor,
+		sidechain,
+		cv,
+		// set up by `serve` along with the bridge client
+		bridge: None,
+		feedback_insert,
+		midi,
+		// sized in the buffer size callback
+		duck_gains: vec![],
+		meters: start_metering(status.clone()),
+		timebase,
+		tap_round: args.tap_round,
+		bypass_fade: args.bypass_fade,
+		bypass_fade_frames: millis_to_frames(args.bypass_fade, sample_rate).round() as usize,
+		smoothing: args.smoothing,
+		transport_gate: args.transport_gate,
+		// the gate closes with the first period if the transport isn't rolling
+		rolling: true,
+		flush_in: None,
+		linked: !args.unlinked,
+		automation: args.automation.clone(),
+		tape_heads: args.heads.is_some(),
+		throw_mode: args.throw,
+		commands: command_rx,
+		status: status.clone(),
+		sample_rate: sample_rate_changes.clone(),
+		failed: false,
+	};
+	Ok(Setup { process, latency_paths, own_inputs, own_outputs, cv_ports, sample_rate: sample_rate_changes, bridge: None })
+}
+
+/// Registers `in1` to `inN` and `out1` to `outM` for the inputs and outputs of the matrix.
+fn set_up_matrix (
+	args: &Args,
+	jack_client: &jack::Client,
+	routing: &Routing,
+	command_rx: QueueReceiver<Control>,
+	status: &Arc<Status>,
+) -> Result<Setup<MatrixProcessor>, Error> {
+	let names = args.port_names();
+	let inputs = (0..routing.inputs())
+		.map(|input| register_port(jack_client, &names.channel("in", input), jack::AudioIn))
+		.collect::<Result<Vec<_>, _>>()?;
+	let outputs = (0..routing.outputs())
+		.map(|output| register_port(jack_client, &names.channel("out", output), jack::AudioOut))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	let sample_rate = jack_client.sample_rate();
+	let timebase = Timebase {
+		sample_rate,
+		tempo: Tempo { bpm: args.bpm, beat_type: 4.0 },
+		speed_of_sound: speed_of_sound(args.temperature, args.humidity),
+	};
+	let mut matrix = DelayMatrix::new(inputs.len());
+	let mut latency_paths = vec![];
+	for &(input, output, delay, gain) in &routing.paths {
+		let frames = delay.to_frames(&timebase);
+		matrix.add_path(input, output, frames, db_to_gain(gain));
+		let latency = if args.report_latency { frames.max(0.0).round() as i64 } else { 0 };
+		latency_paths.push(LatencyPath::new(&inputs[input], &outputs[output], latency)?);
+	}
+
+	let own_inputs = inputs.iter().map(port_name).collect::<Result<Vec<_>, _>>()?;
+	let own_outputs = outputs.iter().map(port_name).collect::<Result<Vec<_>, _>>()?;
+	let process = MatrixProcessor {
+		inputs,
+		outputs,
+		matrix,
+		commands: command_rx,
+		status: status.clone(),
+		workers: args.workers.filter(|&workers| workers > 0).map(WorkerPool::new),
+		failed: false,
+	};
+	let sample_rate = Arc::new(AtomicUsize::new(sample_rate));
+	Ok(Setup { process, latency_paths, own_inputs, own_outputs, cv_ports: vec![], sample_rate, bridge: None })
+}
 
-	// The following code is stolen from Kat Maddox
-	// https://twitter.com/ctrlshifti/status/1288745146759000064
-	let _active_client = match v {
-		0 => ClientDump::ACli(
-			jack_client.activate_async(Notifications::<0>, process).unwrap()
-		),
-		1 => ClientDump::BCli(
-			jack_client.activate_async(Notifications::<1>, process).unwrap()
-		),
-		_ => ClientDump::CCli(
-			jack_client.activate_async(Notifications::<2>, process).unwrap()
-		),
+/// Activates the client with the process handler of `setup`, connects its ports
+/// and serves events until a signal arrives or the server shuts down.
+fn activate<P: ProcessHandler + 'static> (
+	args: &Args,
+	jack_client: jack::Client,
+	setup: Setup<P>,
+	status: &Arc<Status>,
+	events: &std::sync::mpsc::Sender<Event>,
+	event_rx: &std::sync::mpsc::Receiver<Event>,
+	remembered: &mut Vec<(String, String)>,
+) -> Result<Ended, Error> {
+	let Setup { process, latency_paths, own_inputs, own_outputs, cv_ports, sample_rate, mut bridge } = setup;
+	let notifications = Notifications {
+		verbosity: args.verbosity,
+		latency_paths,
+		sample_rate,
+		status: status.clone(),
+		events: events.clone(),
+	};
+	let active_client = jack_client
+		.activate_async(notifications, process)
+		.map_err(|error| Error::Jack("activate the client", error))?;
+	status.active.store(true, Ordering::Relaxed);
+	// ports can only be connected once the client is active
+	let client = active_client.as_client();
+	let connect = args.connect;
+	let peers = |patterns: &[String], ports: usize| -> Vec<Peer> {
+		match (patterns.is_empty(), connect) {
+			(false, _) => patterns.iter().cloned().map(Peer::Pattern).collect(),
+			(true, true) => (0..ports).map(Peer::Physical).collect(),
+			(true, false) => vec![],
+		}
+	};
+	let mut connections = Connections {
+		inputs: peers(&args.connect_in, own_inputs.len()).into_iter().zip(own_inputs.iter().cloned()).collect(),
+		outputs: own_outputs.iter().cloned().zip(peers(&args.connect_out, own_outputs.len())).collect(),
+		own_inputs,
+		own_outputs,
+		remembered: std::mem::take(remembered),
+		verbosity: args.verbosity,
 	};
+	connections.connect(client);
+	describe_ports(client, &cv_ports);
+	let arguments: Vec<String> = std::env::args().skip(1).collect();
+	set_property(client, client.uuid(), ARGUMENTS, &arguments.join(" "), None);
+	// runtime changes are lost along with a client, so they start over with every new one
+	let mut applied: Vec<String> = vec![];
 
-	loop{}
+	// a client nobody connected to within `unused_after` is likely forgotten in a headless rig
+	let unused_after = Duration::from_secs_f64(args.unused_after);
+	let mut unused_since = if args.unused_after > 0.0 && !connections.in_use(client) { Some(Instant::now()) } else { None };
+	// warns once per stretch of being unused
+	let mut warned = false;
+	let signal = loop {
+		let event = match unused_since.filter(|_| !warned) {
+			Some(since) => event_rx.recv_timeout((since + unused_after).saturating_duration_since(Instant::now())),
+			None => event_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+		};
+		match event {
+			Ok(Event::Shutdown(signal)) => break signal,
+			Ok(Event::ServerShutdown(reason)) => {
+				status.active.store(false, Ordering::Relaxed);
+				close_bridge(bridge);
+				// as last seen, the server is gone and can't be asked anymore
+				if let Some(path) = &args.connections {
+					save_connections(path, &connections.remembered, args.verbosity);
+				}
+				*remembered = connections.remembered;
+				// the server is gone along with the client, so neither can be deactivated or closed
+				// and whatever the process callback holds stays allocated
+				std::mem::forget(active_client);
+				return Ok(Ended::ServerShutdown(reason));
+			}
+			Ok(Event::PortRegistered) => connections.connect(active_client.as_client()),
+			Ok(Event::PortRenamed(old_name, new_name)) => {
+				connections.rename(&old_name, &new_name);
+				// the new name may match a pattern the old one didn't
+				connections.connect(active_client.as_client());
+			}
+			Ok(Event::ConnectionsChanged) => {
+				// cleared first so a change coming in while remembering gives another event
+				status.connections_changed.store(false, Ordering::Relaxed);
+				if args.reconnect || args.connections.is_some() {
+					connections.remember(active_client.as_client());
+				}
+			}
+			Ok(Event::Command(line)) => {
+				// only the latest value of each setting is kept, e.g. of `left delay`
+				let setting = |line: &str| line.rsplit_once(char::is_whitespace).map_or(line, |(setting, _)| setting).to_string();
+				applied.retain(|earlier| setting(earlier) != setting(&line));
+				applied.push(line);
+				let client = active_client.as_client();
+				set_property(client, client.uuid(), COMMANDS, &applied.join("\n"), None);
+			}
+			Ok(Event::SaveConnections) => match &args.connections {
+				Some(path) => {
+					connections.remember(active_client.as_client());
+					save_connections(path, &connections.remembered, 1);
+				}
+				None => eprintln!("save needs a file given with --connections"),
+			},
+			Ok(Event::BridgeShutdown(reason)) => {
+				// gone with its server like the client in the case above
+				std::mem::forget(bridge);
+				if let Some(path) = &args.connections {
+					connections.remember(active_client.as_client());
+					save_connections(path, &connections.remembered, args.verbosity);
+				}
+				if let Err(e) = active_client.deactivate() {
+					eprintln!("failed to deactivate the client: {}", e);
+				}
+				return Err(Error::Closed(format!("{} (on the bridge server)", reason)));
+			}
+			Err(RecvTimeoutError::Timeout) if args.exit_if_unused => {
+				close_bridge(bridge.take());
+				if let Some(path) = &args.connections {
+					save_connections(path, &connections.remembered, args.verbosity);
+				}
+				if let Err(e) = active_client.deactivate() {
+					eprintln!("failed to deactivate the client: {}", e);
+				}
+				return Err(Error::Unused(unused_after));
+			}
+			Err(RecvTimeoutError::Timeout) => {
+				eprintln!("warning: none of the ports has been connected for {:?}, nothing goes through the delay", unused_after);
+				warned = true;
+				continue;
+			}
+			// run holds on to a sender for good
+			Err(RecvTimeoutError::Disconnected) => unreachable!(),
+		}
+		if args.unused_after > 0.0 {
+			if connections.in_use(active_client.as_client()) {
+				unused_since = None;
+				warned = false;
+			} else if unused_since.is_none() {
+				unused_since = Some(Instant::now());
+			}
+		}
+	};
+	close_bridge(bridge);
+	if status.stalled.load(Ordering::Relaxed) {
+		// deactivating a client the server doesn't serve anymore can hang just as well
+		std::mem::forget(active_client);
+		return Err(Error::Stalled(Duration::from_secs_f64(args.watchdog)));
+	}
+	if let Some(path) = &args.connections {
+		connections.remember(active_client.as_client());
+		save_connections(path, &connections.remembered, args.verbosity);
+	}
+	if args.verbosity > 0 {
+		println!("shutting down on signal {}", signal);
+	}
+	if let Err(e) = active_client.deactivate() {
+		eprintln!("failed to deactivate the client: {}", e);
+	}
+	Ok(Ended::Signal)
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_automation () {
+		let mut automation = Automation::parse("# seconds delay\n0 120ms\n\n  4.5 1/8d\n").unwrap();
+		assert_eq!(automation.breakpoints, [(0.0, DelayTime::Millis(120.0)), (4.5, DelayTime::Note(0.1875))]);
+		assert_eq!(automation.update(1.0), Some(DelayTime::Millis(120.0)));
+		assert_eq!(automation.update(2.0), None);
+		assert_eq!(automation.update(5.0), Some(DelayTime::Note(0.1875)));
+		// relocating back applies the earlier one again
+		assert_eq!(automation.update(0.0), Some(DelayTime::Millis(120.0)));
+
+		assert_eq!(Automation::parse("1 120ms\n1 240ms").unwrap_err(), "line 2: times have to be ascending");
+		assert!(Automation::parse("-1 120ms").unwrap_err().starts_with("line 1: invalid time"));
+		assert!(Automation::parse("1 120ms 3").is_err());
+		assert!(Automation::parse("1 soon").is_err());
+		assert!(Automation::load("/nonexistent/automation").is_err());
+	}
+
+	#[test]
+	fn parses_routing () {
+		let routing = Routing::parse("# input output delay [gain in dB]\n1 1 10ms\n2 3 4800 -6\n").unwrap();
+		assert_eq!(routing.paths, [(0, 0, DelayTime::Millis(10.0), 0.0), (1, 2, DelayTime::Frames(4800.0), -6.0)]);
+		assert_eq!((routing.inputs(), routing.outputs()), (2, 3));
+
+		assert_eq!(Routing::parse("# nothing\n").unwrap_err(), "the matrix has no paths");
+		assert!(Routing::parse("0 1 10ms").unwrap_err().contains("counted from 1"));
+		assert!(Routing::parse("1 1").is_err());
+		assert!(Routing::parse("1 1 10ms loud").is_err());
+		assert!(Routing::parse("1 1 10ms -6 extra").is_err());
+		assert!(Routing::load("/nonexistent/matrix").is_err());
+	}
+
+	#[test]
+	fn parses_units () {
+		assert_eq!("front:12ms".parse(), Ok(UnitSpec { name: "front".to_string(), delay: DelayTime::Millis(12.0) }));
+		assert!("front".parse::<UnitSpec>().is_err());
+		assert!(":12ms".parse::<UnitSpec>().is_err());
+		assert!("front left:12ms".parse::<UnitSpec>().is_err());
+		assert!("front:later".parse::<UnitSpec>().is_err());
+	}
+
+	#[test]
+	fn parses_taps () {
+		assert_eq!("250ms".parse(), Ok(TapSpec { time: DelayTime::Millis(250.0), gain: 1.0, pan: None }));
+		assert_eq!("1/4:0.5:-1".parse(), Ok(TapSpec { time: DelayTime::Note(0.25), gain: 0.5, pan: Some(-1.0) }));
+		assert!("250ms:loud".parse::<TapSpec>().is_err());
+		assert!("250ms:0.5:1.5".parse::<TapSpec>().is_err());
+		assert!("250ms:0.5:left".parse::<TapSpec>().is_err());
+	}
+
+	#[test]
+	fn parses_head_modes_and_notes () {
+		assert_eq!(parse_heads("1"), Ok(1));
+		assert_eq!(parse_heads("7"), Ok(7));
+		assert!(parse_heads("0").is_err());
+		assert!(parse_heads("8").is_err());
+		assert_eq!(head_gains(4), [0.0, 1.0, 1.0]);
+
+		assert_eq!(parse_note("60"), Ok(60));
+		assert!(parse_note("128").is_err());
+		assert_eq!(note_state(&[0x93, 60, 100], 60), Some(true));
+		assert_eq!(note_state(&[0x90, 60, 0], 60), Some(false));
+		assert_eq!(note_state(&[0x80, 60, 64], 60), Some(false));
+		assert_eq!(note_state(&[0x90, 61, 100], 60), None);
+		assert_eq!(note_state(&[0xb0, 60, 127], 60), None);
+	}
+
+	#[test]
+	fn parses_latency_ranges () {
+		assert_eq!("10ms:14ms".parse(), Ok(LatencyRange { min: DelayTime::Millis(10.0), max: DelayTime::Millis(14.0) }));
+		assert!("10ms".parse::<LatencyRange>().is_err());
+		assert!("10ms:soon".parse::<LatencyRange>().is_err());
+	}
+
+	#[test]
+	fn loads_saved_connections () {
+		let path = std::env::temp_dir().join(format!("jack-delay-connections-{}", std::process::id()));
+		assert_eq!(load_connections(&path), Ok(vec![]));
+		let connections = vec![
+			("system:capture_1".to_string(), "delay:in1".to_string()),
+			("delay:out1".to_string(), "mixer:bus in 1".to_string()),
+		];
+		save_connections(&path, &connections, 0);
+		assert_eq!(load_connections(&path), Ok(connections));
+		std::fs::write(&path, "# jack-delay connections\nsystem:capture_1 delay:in1\n").unwrap();
+		assert!(load_connections(&path).unwrap_err().ends_with("line 2: expected \"<source>\\t<destination>\""));
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn worker_pool_runs_every_task_once () {
+		let mut pool = WorkerPool::new(3);
+		let counts: Vec<AtomicUsize> = (0..64).map(|_| AtomicUsize::new(0)).collect();
+		for _ in 0..100 {
+			pool.run(counts.len(), &|index| {
+				counts[index].fetch_add(1, Ordering::Relaxed);
+			});
+		}
+		assert!(counts.iter().all(|count| count.load(Ordering::Relaxed) == 100));
+		// a failing task fails the period once the others are done, and the next one runs as usual
+		let failed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| pool.run(8, &|index| assert_ne!(index, 5))));
+		assert!(failed.is_err());
+		pool.run(counts.len(), &|index| {
+			counts[index].fetch_add(1, Ordering::Relaxed);
+		});
+		assert!(counts.iter().all(|count| count.load(Ordering::Relaxed) == 101));
+	}
 
+	#[test]
+	fn renamed_ports_keep_their_connections () {
+		let mut connections = Connections {
+			inputs: vec![(Peer::Physical(0), "delay:in1".to_string())],
+			outputs: vec![("delay:out1".to_string(), Peer::Pattern("mixer:.*".to_string()))],
+			own_inputs: vec!["delay:in1".to_string()],
+			own_outputs: vec!["delay:out1".to_string()],
+			remembered: vec![
+				("system:capture_1".to_string(), "delay:in1".to_string()),
+				("delay:out1".to_string(), "mixer:a".to_string()),
+				("delay:out1".to_string(), "mixer:b".to_string()),
+			],
+			verbosity: 0,
+		};
+		connections.rename("mixer:a", "mixer:b");
+		connections.rename("delay:in1", "delay:left");
+		assert_eq!(connections.own_inputs, ["delay:left"]);
+		assert_eq!(connections.inputs[0].1, "delay:left");
+		assert_eq!(connections.own_outputs, ["delay:out1"]);
+		// a connection renamed into one already remembered is kept once
+		assert_eq!(connections.remembered, [
+			("system:capture_1".to_string(), "delay:left".to_string()),
+			("delay:out1".to_string(), "mixer:b".to_string()),
+		]);
+	}
+}